@@ -1,38 +1,247 @@
 // Assume all Vec<u8> in ASTs are l-stripped and r-stripped of '\s\t' | ';'
 pub enum Ast {
-    Block(Vec<Ast>),  // list of ASTs
-    Comment(Vec<u8>), // text after #
+    Block(Vec<Ast>),          // list of ASTs
+    Comment(Vec<u8>),         // text after #
+    TrailingComment(Vec<u8>), // text after `;#`, on the line of the command before it
     Procedure {
         name: Vec<u8>,
+        qualified_name: Vec<u8>, // such as `::gtp::parse_ie`, for a proc inside `namespace eval gtp`
         parameters: Vec<Vec<u8>>,
         body: Box<Ast>,
     },
+    Namespace {
+        name: Vec<u8>,
+        body: Box<Ast>,
+    },
     If {
-        condition_body_clauses: Vec<(Vec<u8>, Ast)>,
+        condition_body_clauses: Vec<(Word, Ast)>, // a braced condition is a Word::Expression
         maybe_block_if_false: Option<Box<Ast>>,
     },
     Switch {
-        condition: Vec<u8>,
-        value_block_or_fallthrough_vec: Vec<(Vec<u8>, Option<Ast>)>,
+        options: Vec<SwitchOption>,
+        subject: Box<Word>,
+        arms: Vec<SwitchArm>,
     },
     When {
         event_name: Vec<u8>,
+        maybe_priority: Option<u16>, // 0 to 1000
+        maybe_timing: Option<bool>,  // on or off
+        body: Box<Ast>,
+    },
+    Catch {
         body: Box<Ast>,
+        maybe_result_variable: Option<Vec<u8>>,
+        maybe_options_variable: Option<Vec<u8>>,
+    },
+    Try {
+        body: Box<Ast>,
+        handlers: Vec<TryHandler>,
+        maybe_finally: Option<Box<Ast>>,
+    },
+    Command {
+        name: Vec<u8>,
+        maybe_subcommand: Option<Vec<u8>>, // such as `insert` in `HTTP::header insert`
+        words: Vec<Word>,                  // arguments, without the name and sub-command
     },
+    Gtp(Box<GtpCommand>),
+    Udp(Box<UdpCommand>),
     Statement(Statement),
+    Verbatim(Vec<u8>), // raw lines protected by a `# tclfmt: off` or `# tclfmt: skip` comment
     EmptyLine,
-    // TODO: GTP/UDP func calls
+}
+
+pub enum Word {
+    Bare(Vec<WordPart>),
+    Quoted(Vec<WordPart>),    // parts between the quotes
+    Braced(Vec<u8>),          // text between the brackets, verbatim
+    Script(Ast),              // braced word that the command signature declares a script
+    Expression(Vec<u8>),      // braced word that the command signature declares an expression
+    List(Vec<Vec<u8>>), // elements of a braced word that the command signature declares a list
+    CommandSubstitution(Ast), // script between the square brackets
+    Variable(Variable),
+    Expansion(Box<Word>), // word after {*}
+}
+
+impl Word {
+    // the text of the word, if it doesn't depend on any substitution
+    pub fn literal_text(&self) -> Option<Vec<u8>> {
+        match self {
+            Word::Bare(parts) | Word::Quoted(parts) => match parts.as_slice() {
+                [] => Some(Vec::new()),
+                [WordPart::Text(text)] if !text.contains(&b'\\') => Some(text.clone()),
+                _ => None,
+            },
+            Word::Braced(text) => Some(text.clone()),
+            _ => None,
+        }
+    }
+
+    // Tcl compares the text of a switch pattern, so `"default"` and `{default}` count too
+    pub fn is_default(&self) -> bool {
+        self.literal_text().is_some_and(|text| text == b"default")
+    }
+}
+
+pub enum WordPart {
+    Text(Vec<u8>),
+    Variable(Variable),
+    CommandSubstitution(Ast), // script between the square brackets
+}
+
+// `$name`, `${name}`, `$arr(key)`, `$::ns::var` or `$static::x`
+pub struct Variable {
+    pub maybe_namespace: Option<Vec<u8>>, // qualifier before the last ::, empty for `$::var`
+    pub name: Vec<u8>,
+    pub maybe_index: Option<Vec<WordPart>>, // parts between the parens of an array element
+    pub is_braced: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SwitchOption {
+    Exact,
+    Glob,
+    Regexp,
+    NoCase,
+    EndOfOptions, // --
+}
+
+pub enum SwitchArm {
+    Pattern {
+        pattern: Word,
+        maybe_body: Option<Ast>, // a missing body falls through to the next arm, i.e. `-`
+    },
+    Comment(Vec<u8>),  // text after #, Tcl reads it as more patterns and bodies
+    Verbatim(Vec<u8>), // arms protected by a `# tclfmt: off` or `# tclfmt: skip` comment
+}
+
+// `on code {variables} body` or `trap pattern {variables} body`
+// a missing body means the handler falls through to the next one, i.e. `-`
+pub enum TryHandler {
+    On {
+        code: Vec<u8>,
+        variables: Vec<Vec<u8>>,
+        maybe_body: Option<Ast>,
+    },
+    Trap {
+        pattern: Vec<Vec<u8>>,
+        variables: Vec<Vec<u8>>,
+        maybe_body: Option<Ast>,
+    },
+}
+
+// `-name value`, such as `-message $msg` or `-code error`
+pub type CommandOption = (Vec<u8>, Word);
+
+// F5 GTP:: commands
+pub enum GtpCommand {
+    Header {
+        arguments: Vec<GtpArgument>, // the field and the options around it
+    },
+    Ie {
+        action: GtpIeAction,
+        arguments: Vec<GtpArgument>,
+    },
+    Parse {
+        payload: Word,
+        maybe_length: Option<Word>,
+    },
+    New {
+        version: Word,
+        message_type: Word,
+    },
+    Respond {
+        message: Word,
+    },
+    Forward {
+        message: Word,
+    },
+}
+
+// options are kept where they were given, between the other arguments
+pub enum GtpArgument {
+    Option(CommandOption),
+    Word(Word),
+}
+
+#[derive(Clone, Copy)]
+pub enum GtpIeAction {
+    Get,
+    Count,
+    Insert,
+    Remove,
+    Set,
+}
+
+// F5 UDP:: commands
+pub enum UdpCommand {
+    Payload {
+        arguments: Vec<Word>, // `[<offset>] [<length>]`
+    },
+    PayloadReplace {
+        offset: Word,
+        length: Word,
+        data: Word,
+    },
+    Respond {
+        data: Word,
+    },
+    Drop,
 }
 
 pub enum Statement {
-    Set { identifier: Vec<u8>, value: Vec<u8> },
-    Log { bucket: Vec<u8>, value: Vec<u8> },
-    Snat { ip_address: Vec<u8>, port: Vec<u8> },
-    Node { ip_address: Vec<u8>, port: Vec<u8> },
-    Pool { identifier: Vec<u8> },
-    SnatPool { identifier: Vec<u8> },
-    Return { value: Option<Vec<u8>> },
-    Other { data: Vec<u8> },
+    // `set <variable> [<value>]`, a read without the value
+    Set {
+        target: Variable,
+        maybe_value: Option<Box<Word>>,
+    },
+    // `log [-noname] [<destination>] [<facility>.<level>] <message>`
+    Log {
+        flags: Vec<Vec<u8>>,
+        maybe_destination: Option<Vec<u8>>, // remote syslog server, `ip[:port]`
+        maybe_facility: Option<Vec<u8>>,    // such as `local0.` or `local0.info`
+        message: Box<Word>,
+    },
+    Snat {
+        target: SnatTarget,
+    },
+    Node {
+        endpoint: Box<Endpoint>,
+    },
+    Pool {
+        name: Box<Word>,
+        maybe_member: Option<Box<Endpoint>>, // `member <address> [<port>]`
+    },
+    SnatPool {
+        name: Box<Word>,
+        maybe_member: Option<Box<Endpoint>>,
+    },
+    // `return [-option value ...] [<value>]`
+    Return {
+        options: Vec<CommandOption>,
+        maybe_value: Option<Box<Word>>,
+    },
+}
+
+pub enum SnatTarget {
+    Automap,
+    None,
+    Endpoint(Box<Endpoint>),
+}
+
+// an address, with the port either in the address or as the next word
+pub struct Endpoint {
+    pub address: Address,
+    pub maybe_port: Option<Box<Word>>,
+}
+
+pub enum Address {
+    // `10.0.0.1%2:80`, or `2001:db8::1%2.80` where IPv6 takes a dot before the port
+    Literal {
+        ip: Vec<u8>,
+        maybe_route_domain: Option<Vec<u8>>,
+        maybe_port: Option<Vec<u8>>,
+    },
+    Word(Box<Word>), // such as `$ip` or `[LB::server addr]`
 }
 
 impl std::fmt::Debug for Ast {
@@ -40,8 +249,19 @@ impl std::fmt::Debug for Ast {
         match self {
             Self::Block(trees) => write!(f, "Ast::Block of {} trees", trees.len()),
             Self::Comment(_) => write!(f, "Ast::Comment"),
-            Self::Procedure { parameters, .. } => {
-                write!(f, "Ast::Procedure with {} parameters", parameters.len())
+            Self::TrailingComment(_) => write!(f, "Ast::TrailingComment"),
+            Self::Procedure {
+                qualified_name,
+                parameters,
+                ..
+            } => write!(
+                f,
+                "Ast::Procedure ({}) with {} parameters",
+                String::from_utf8_lossy(qualified_name),
+                parameters.len()
+            ),
+            Self::Namespace { name, .. } => {
+                write!(f, "Ast::Namespace ({})", String::from_utf8_lossy(name))
             }
             Self::If {
                 condition_body_clauses,
@@ -52,9 +272,12 @@ impl std::fmt::Debug for Ast {
                 (x, None) => write!(f, "Ast::If (if-elseif[{}])", x),
                 (x, Some(_)) => write!(f, "Ast::If (if-elseif[{}]-else)", x),
             },
-            Self::Switch { condition, .. } => {
-                write!(f, "Ast::Switch with {} conditions", condition.len())
-            }
+            Self::Switch { options, arms, .. } => write!(
+                f,
+                "Ast::Switch with {} options and {} arms",
+                options.len(),
+                arms.len()
+            ),
             Self::Statement(s) => match s {
                 Statement::Set { .. } => write!(f, "Ast::Statement::Set"),
                 Statement::Log { .. } => write!(f, "Ast::Statement::Log"),
@@ -62,16 +285,68 @@ impl std::fmt::Debug for Ast {
                 Statement::Node { .. } => write!(f, "Ast::Statement::Node"),
                 Statement::Pool { .. } => write!(f, "Ast::Statement::Pool"),
                 Statement::SnatPool { .. } => write!(f, "Ast::Statement::SnatPool"),
-                Statement::Return { value } if value.is_some() => {
+                Statement::Return { maybe_value, .. } if maybe_value.is_some() => {
                     write!(f, "Ast::Statement::Return with value")
                 }
                 Statement::Return { .. } => write!(f, "Ast::Statement::Return"),
-                Statement::Other { data } => {
-                    write!(f, "Ast::Statement::Other with length {}", data.len())
+            },
+            Self::Catch {
+                maybe_result_variable,
+                ..
+            } => match maybe_result_variable {
+                Some(_) => write!(f, "Ast::Catch with result variable"),
+                None => write!(f, "Ast::Catch"),
+            },
+            Self::Try {
+                handlers,
+                maybe_finally,
+                ..
+            } => match maybe_finally {
+                Some(_) => write!(f, "Ast::Try with {} handlers and finally", handlers.len()),
+                None => write!(f, "Ast::Try with {} handlers", handlers.len()),
+            },
+            Self::Command {
+                name,
+                maybe_subcommand,
+                words,
+            } => match maybe_subcommand {
+                Some(subcommand) => write!(
+                    f,
+                    "Ast::Command ({} {}) with {} words",
+                    String::from_utf8_lossy(name),
+                    String::from_utf8_lossy(subcommand),
+                    words.len()
+                ),
+                None => write!(
+                    f,
+                    "Ast::Command ({}) with {} words",
+                    String::from_utf8_lossy(name),
+                    words.len()
+                ),
+            },
+            Self::Gtp(c) => match c.as_ref() {
+                GtpCommand::Header { .. } => write!(f, "Ast::Gtp::Header"),
+                GtpCommand::Ie { arguments, .. } => {
+                    write!(f, "Ast::Gtp::Ie with {} arguments", arguments.len())
+                }
+                GtpCommand::Parse { .. } => write!(f, "Ast::Gtp::Parse"),
+                GtpCommand::New { .. } => write!(f, "Ast::Gtp::New"),
+                GtpCommand::Respond { .. } => write!(f, "Ast::Gtp::Respond"),
+                GtpCommand::Forward { .. } => write!(f, "Ast::Gtp::Forward"),
+            },
+            Self::Udp(c) => match c.as_ref() {
+                UdpCommand::Payload { arguments } => {
+                    write!(f, "Ast::Udp::Payload with {} arguments", arguments.len())
                 }
+                UdpCommand::PayloadReplace { .. } => write!(f, "Ast::Udp::PayloadReplace"),
+                UdpCommand::Respond { .. } => write!(f, "Ast::Udp::Respond"),
+                UdpCommand::Drop => write!(f, "Ast::Udp::Drop"),
             },
+            Self::Verbatim(data) => write!(f, "Ast::Verbatim of {} bytes", data.len()),
             Self::EmptyLine => write!(f, "Ast::EmptyLine"),
-            Self::When { event_name, .. } => write!(f, "Ast::When ({})", String::from_utf8_lossy(event_name)),
+            Self::When { event_name, .. } => {
+                write!(f, "Ast::When ({})", String::from_utf8_lossy(event_name))
+            }
         }
     }
 }
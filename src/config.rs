@@ -0,0 +1,255 @@
+// Formatter configuration, read from an ini-like file:
+//
+//   [signatures]
+//   after   <word> -periodic? <script>?
+//
+//   [style]
+//   switch_patterns = quoted
+//   normalize_switches = true
+//   switch_comments = move
+//   max_width = 100
+//   warn_encoding = true
+//   line_endings = auto
+//   final_newline = one
+//
+// Lines starting with # are comments.
+
+use std::path::Path;
+
+use crate::signature::{SignatureFail, Signatures};
+
+#[derive(Debug)]
+pub enum ConfigFail {
+    Io(std::io::Error),
+    UnknownSection(Vec<u8>),
+    UnknownOption(Vec<u8>), // key of a `key = value` line
+    InvalidValue(Vec<u8>),  // value of a `key = value` line
+    Signature(SignatureFail),
+}
+type Result<T> = std::result::Result<T, ConfigFail>;
+
+impl std::fmt::Display for ConfigFail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::UnknownSection(name) => {
+                write!(f, "unknown section [{}]", String::from_utf8_lossy(name))
+            }
+            Self::UnknownOption(key) => {
+                write!(f, "unknown option {}", String::from_utf8_lossy(key))
+            }
+            Self::InvalidValue(value) => {
+                write!(f, "invalid value {}", String::from_utf8_lossy(value))
+            }
+            Self::Signature(e) => write!(f, "signatures: {}", e),
+        }
+    }
+}
+
+pub struct Config {
+    pub signatures: Signatures,
+    pub style: Style,
+}
+
+// how the formatter writes constructs that have more than one valid spelling
+#[derive(Clone)]
+pub struct Style {
+    pub switch_patterns: PatternStyle,
+    pub normalize_switches: bool, // sort fallthrough labels and warn about unreachable arms
+    pub move_switch_comments: bool, // into the body of the next arm, where Tcl sees a comment
+    pub max_width: usize,         // longer braced lists are written one element per line
+    pub warn_encoding: bool,      // report lines that are not valid UTF-8
+    pub line_endings: LineEnding,
+    pub final_newline: FinalNewline,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            switch_patterns: PatternStyle::default(),
+            normalize_switches: false,
+            move_switch_comments: false,
+            max_width: 100,
+            warn_encoding: false,
+            line_endings: LineEnding::default(),
+            final_newline: FinalNewline::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum PatternStyle {
+    #[default]
+    Preserve,
+    Quoted, // "pattern"
+    Braced, // {pattern}
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Auto, // whichever the input uses most
+    Lf,
+    Crlf,
+    Native, // crlf on Windows, lf elsewhere
+}
+
+impl LineEnding {
+    // the ending to write for this input, a tie goes to lf
+    pub fn resolve(self, buf: &[u8]) -> Self {
+        match self {
+            Self::Auto => {
+                let lines = buf.iter().filter(|&&c| c == b'\n').count();
+                let crlf_lines = buf.windows(2).filter(|w| w == b"\r\n").count();
+                match crlf_lines * 2 > lines {
+                    true => Self::Crlf,
+                    false => Self::Lf,
+                }
+            }
+            Self::Native if cfg!(windows) => Self::Crlf,
+            Self::Native => Self::Lf,
+            ending => ending,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum FinalNewline {
+    #[default]
+    Keep, // as the input ends, with or without a newline
+    One,  // exactly one, the empty lines at the end of the file are dropped
+    Omit, // what keep is for an input without a final newline
+}
+
+impl FinalNewline {
+    pub fn resolve(self, buf: &[u8]) -> Self {
+        match self {
+            Self::Keep if !buf.is_empty() && !buf.ends_with(b"\n") => Self::Omit,
+            policy => policy,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self {
+            signatures: Signatures::builtin(),
+            style: Style::default(),
+        }
+    }
+
+    // a missing file is the default configuration
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(buf) => Config::parse(&buf),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::new()),
+            Err(e) => Err(ConfigFail::Io(e)),
+        }
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let mut config = Config::new();
+        for (section, body) in split_sections(buf)? {
+            match section.as_slice() {
+                b"signatures" => {
+                    let signatures = Signatures::parse(&body).map_err(ConfigFail::Signature)?;
+                    config.signatures.extend(signatures);
+                }
+                b"style" => {
+                    for line in body.split(|&c| c == b'\n').filter(|l| !l.is_empty()) {
+                        config.style.set(line)?;
+                    }
+                }
+                _ => return Err(ConfigFail::UnknownSection(section)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl Style {
+    // a single `key = value` line
+    fn set(&mut self, line: &[u8]) -> Result<()> {
+        let Some(idx) = line.iter().position(|&c| c == b'=') else {
+            return Err(ConfigFail::UnknownOption(line.to_vec()));
+        };
+        let (key, value) = (line[..idx].trim_ascii(), line[idx + 1..].trim_ascii());
+        match key {
+            b"switch_patterns" => {
+                self.switch_patterns = match value {
+                    b"preserve" => PatternStyle::Preserve,
+                    b"quoted" => PatternStyle::Quoted,
+                    b"braced" => PatternStyle::Braced,
+                    _ => return Err(ConfigFail::InvalidValue(value.to_vec())),
+                }
+            }
+            b"normalize_switches" => {
+                self.normalize_switches = match value {
+                    b"true" => true,
+                    b"false" => false,
+                    _ => return Err(ConfigFail::InvalidValue(value.to_vec())),
+                }
+            }
+            b"switch_comments" => {
+                self.move_switch_comments = match value {
+                    b"keep" => false,
+                    b"move" => true,
+                    _ => return Err(ConfigFail::InvalidValue(value.to_vec())),
+                }
+            }
+            b"warn_encoding" => {
+                self.warn_encoding = match value {
+                    b"true" => true,
+                    b"false" => false,
+                    _ => return Err(ConfigFail::InvalidValue(value.to_vec())),
+                }
+            }
+            b"line_endings" => {
+                self.line_endings = match value {
+                    b"auto" => LineEnding::Auto,
+                    b"lf" => LineEnding::Lf,
+                    b"crlf" => LineEnding::Crlf,
+                    b"native" => LineEnding::Native,
+                    _ => return Err(ConfigFail::InvalidValue(value.to_vec())),
+                }
+            }
+            b"final_newline" => {
+                self.final_newline = match value {
+                    b"keep" => FinalNewline::Keep,
+                    b"one" => FinalNewline::One,
+                    _ => return Err(ConfigFail::InvalidValue(value.to_vec())),
+                }
+            }
+            b"max_width" => {
+                self.max_width = std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| ConfigFail::InvalidValue(value.to_vec()))?
+            }
+            _ => return Err(ConfigFail::UnknownOption(key.to_vec())),
+        }
+        Ok(())
+    }
+}
+
+// (section name, lines of the section), comment lines are dropped
+fn split_sections(buf: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut sections: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    for line in buf.split(|&c| c == b'\n') {
+        let line = line.trim_ascii();
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        match line.strip_prefix(b"[").and_then(|x| x.strip_suffix(b"]")) {
+            Some(name) => sections.push((name.trim_ascii().to_vec(), Vec::new())),
+            None => match sections.last_mut() {
+                Some((_, body)) => {
+                    body.extend_from_slice(line);
+                    body.push(b'\n');
+                }
+                None => return Err(ConfigFail::UnknownSection(Vec::new())),
+            },
+        }
+    }
+    Ok(sections)
+}
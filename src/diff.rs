@@ -0,0 +1,308 @@
+// Differences between the input and the formatted output, as edits of the input.
+// Both buffers are split into lines, or into chunks of whitespace, line endings and everything
+// in between, and matched with Myers' algorithm, which is fast when the two are mostly the same.
+
+use std::ops::Range;
+
+// replace the bytes of the input in `range` by `replacement`
+pub struct Edit {
+    pub range: Range<usize>,
+    pub replacement: Vec<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    Line,  // whole lines, each with its line ending
+    Token, // whitespace, line endings and the words between them
+}
+
+// non-overlapping and in order
+pub fn edits(original: &[u8], formatted: &[u8], granularity: Granularity) -> Vec<Edit> {
+    let split = match granularity {
+        Granularity::Line => lines,
+        Granularity::Token => chunks,
+    };
+    let (old, new) = (split(original), split(formatted));
+    let mut edits: Vec<Edit> = Vec::new();
+    let mut last_equal = (0, 0);
+    for (old_idx, new_idx) in matching(&old, &new, original, formatted)
+        .into_iter()
+        .chain([(old.len(), new.len())])
+    {
+        if (old_idx, new_idx) != last_equal {
+            let start = old.get(last_equal.0).map_or(original.len(), |c| c.start);
+            let end = old.get(old_idx).map_or(original.len(), |c| c.start);
+            let replacement = new[last_equal.1..new_idx]
+                .iter()
+                .flat_map(|c| formatted[c.clone()].iter().copied())
+                .collect();
+            edits.push(Edit {
+                range: start..end,
+                replacement,
+            });
+        }
+        last_equal = (old_idx + 1, new_idx + 1);
+    }
+    edits
+}
+
+// where offsets of the input, such as cursor positions, end up in the formatted output
+// an offset in a token that is kept moves with the token, an offset in text that is replaced
+// keeps its distance from the start of the replacement, as far as the replacement goes
+pub fn map_offsets(original: &[u8], formatted: &[u8], offsets: &[usize]) -> Vec<usize> {
+    let (old, new) = (chunks(original), chunks(formatted));
+    let pairs = matching(&old, &new, original, formatted);
+    offsets
+        .iter()
+        .map(|&offset| {
+            let x = old.partition_point(|c| c.end <= offset);
+            match pairs.binary_search_by_key(&x, |&(x, _)| x) {
+                Ok(idx) => new[pairs[idx].1].start + offset - old[x].start,
+                Err(idx) => {
+                    let (old_start, new_start) = match idx.checked_sub(1).map(|i| pairs[i]) {
+                        Some((x, y)) => (old[x].end, new[y].end),
+                        None => (0, 0),
+                    };
+                    let new_end = pairs
+                        .get(idx)
+                        .map_or(formatted.len(), |&(_, y)| new[y].start);
+                    (new_start + offset.saturating_sub(old_start)).min(new_end)
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn apply(original: &[u8], edits: &[Edit]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(original.len());
+    let mut idx = 0;
+    for edit in edits {
+        buf.extend_from_slice(&original[idx..edit.range.start]);
+        buf.extend_from_slice(&edit.replacement);
+        idx = edit.range.end;
+    }
+    buf.extend_from_slice(&original[idx..]);
+    buf
+}
+
+// `[{"start": 4, "end": 6, "replacement": "    "}]`, offsets are bytes of the input
+// a replacement that isn't UTF-8 can't be a JSON string, it's `"replacement_base64"` instead
+pub fn to_json(edits: &[Edit]) -> String {
+    let edits: Vec<String> = edits
+        .iter()
+        .map(|edit| {
+            let replacement = match std::str::from_utf8(&edit.replacement) {
+                Ok(text) => format!("\"replacement\": {}", json_string(text)),
+                Err(_) => format!("\"replacement_base64\": \"{}\"", base64(&edit.replacement)),
+            };
+            format!(
+                "{{\"start\": {}, \"end\": {}, {}}}",
+                edit.range.start, edit.range.end, replacement
+            )
+        })
+        .collect();
+    format!("[{}]", edits.join(", "))
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut s = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            c if (c as u32) < 0x20 => s.push_str(&format!("\\u{:04x}", c as u32)),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
+
+// standard alphabet, with padding
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut s = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bits = group
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (idx, &c)| bits | (c as u32) << (16 - 8 * idx));
+        for idx in 0..4 {
+            match idx <= group.len() {
+                true => s.push(ALPHABET[(bits >> (18 - 6 * idx) & 0x3f) as usize] as char),
+                false => s.push('='),
+            }
+        }
+    }
+    s
+}
+
+// byte ranges of the lines, each with its line ending
+pub fn lines(buf: &[u8]) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (idx, _) in buf.iter().enumerate().filter(|(_, &c)| c == b'\n') {
+        lines.push(start..idx + 1);
+        start = idx + 1;
+    }
+    if start < buf.len() {
+        lines.push(start..buf.len());
+    }
+    lines
+}
+
+// byte ranges of runs of spaces and tabs, line endings and runs of anything else
+fn chunks(buf: &[u8]) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < buf.len() {
+        let rest = &buf[start..];
+        let len = match rest {
+            [b'\n', ..] => 1,
+            [b'\r', b'\n', ..] => 2,
+            [b' ' | b'\t', ..] => rest
+                .iter()
+                .take_while(|&&c| matches!(c, b' ' | b'\t'))
+                .count(),
+            _ => rest
+                .iter()
+                .take_while(|&&c| !matches!(c, b' ' | b'\t' | b'\n'))
+                .count(),
+        };
+        // a \r not followed by \n is part of the next run
+        let len = match len {
+            len if rest[..len].ends_with(b"\r") && rest.get(len) == Some(&b'\n') => len - 1,
+            len => len,
+        };
+        chunks.push(start..start + len);
+        start += len;
+    }
+    chunks
+}
+
+// indexes of the chunks that are kept, in order
+fn matching(
+    old: &[Range<usize>],
+    new: &[Range<usize>],
+    original: &[u8],
+    formatted: &[u8],
+) -> Vec<(usize, usize)> {
+    let is_equal = |x: usize, y: usize| original[old[x].clone()] == formatted[new[y].clone()];
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let offset = n + m + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // the furthest x of each diagonal k before round d, for -d <= k <= d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    'search: for d in 0..=(n + m) {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = match k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                true => v[idx + 1],
+                false => v[idx - 1] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && is_equal(x as usize, y as usize) {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // walk back from the end, collecting the diagonals
+    let mut pairs = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let (d, k) = (d as isize, x - y);
+        let at = |k: isize| v[(k + d) as usize];
+        let prev_k = match k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let prev_x = match d {
+            0 => 0,
+            _ => at(prev_k),
+        };
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y.max(0) {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        if d > 0 {
+            (x, y) = (prev_x, prev_y);
+        }
+    }
+    pairs.reverse();
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &[u8] = b"when HTTP_REQUEST {\n  set a  1\n\n\n  log local0. $a\n}\n";
+    const FORMATTED: &[u8] = b"when HTTP_REQUEST {\n    set a 1\n\n    log local0. $a\n}\n";
+
+    #[test]
+    fn applying_the_edits_gives_the_formatted_text() {
+        for granularity in [Granularity::Line, Granularity::Token] {
+            let edits = edits(ORIGINAL, FORMATTED, granularity);
+            assert_eq!(apply(ORIGINAL, &edits), FORMATTED);
+            assert!(edits.windows(2).all(|e| e[0].range.end <= e[1].range.start));
+        }
+        assert_eq!(
+            apply(b"", &edits(b"", b"set a 1\n", Granularity::Token)),
+            b"set a 1\n"
+        );
+        assert_eq!(
+            apply(b"set a 1\n", &edits(b"set a 1\n", b"", Granularity::Line)),
+            b""
+        );
+    }
+
+    #[test]
+    fn edits_only_cover_what_changed() {
+        assert!(edits(ORIGINAL, ORIGINAL, Granularity::Token).is_empty());
+        let edits = edits(b"set a  1\n", b"set a 1\n", Granularity::Token);
+        let [edit] = edits.as_slice() else {
+            panic!("expected one edit");
+        };
+        assert_eq!(edit.range, 5..7);
+        assert_eq!(edit.replacement, b" ");
+    }
+
+    #[test]
+    fn offsets_move_with_their_text() {
+        let log = ORIGINAL.windows(3).position(|w| w == b"log").unwrap();
+        let moved_log = FORMATTED.windows(3).position(|w| w == b"log").unwrap();
+        let offsets = [0, log + 1, ORIGINAL.len()];
+        assert_eq!(
+            map_offsets(ORIGINAL, FORMATTED, &offsets),
+            [0, moved_log + 1, FORMATTED.len()]
+        );
+        // in replaced whitespace, as far as the replacement goes
+        assert_eq!(map_offsets(b"set a  1", b"set a 1", &[6, 7]), [6, 6]);
+    }
+
+    #[test]
+    fn replacements_that_are_not_utf8_are_base64() {
+        let edits = [Edit {
+            range: 0..2,
+            replacement: b"  caf\xe9".to_vec(),
+        }];
+        assert_eq!(
+            to_json(&edits),
+            r#"[{"start": 0, "end": 2, "replacement_base64": "ICBjYWbp"}]"#
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+}
@@ -1,25 +1,57 @@
-use std::iter;
+use std::ops::Range;
 
-use crate::ast::{Ast, Statement};
+use crate::{
+    ast::{
+        Address, Ast, Endpoint, GtpArgument, GtpCommand, GtpIeAction, SnatTarget, Statement,
+        SwitchArm, SwitchOption, TryHandler, UdpCommand, Variable, Word, WordPart,
+    },
+    config::{FinalNewline, LineEnding, PatternStyle, Style},
+};
 
 pub struct Formatter {
     depth: usize,
     consecutive_empty_lines: usize,
     buf: Vec<u8>,
+    style: Style,
+    verbatim: Vec<Range<usize>>, // of the buffer, left with the line endings they were read with
 }
 
 impl Formatter {
-    pub fn new() -> Self {
+    pub fn with_style(style: Style) -> Self {
         Self {
             depth: 0,
             consecutive_empty_lines: 0,
             buf: Vec::new(),
+            style,
+            verbatim: Vec::new(),
         }
     }
 
+    // formats a part of the tree on its own, to be written inline
+    fn sub_formatter(&self) -> Self {
+        Formatter::with_style(self.style.clone())
+    }
+
+    // lines end with \n until the whole file is written, an unresolved auto is lf
     pub fn format(mut self, ast: Ast) -> Vec<u8> {
         self.run(ast);
-        self.buf
+        match self.style.final_newline {
+            FinalNewline::Keep => {}
+            FinalNewline::One => {
+                while self.buf.ends_with(b"\n\n") {
+                    self.buf.pop();
+                }
+            }
+            FinalNewline::Omit => {
+                while self.buf.ends_with(b"\n") {
+                    self.buf.pop();
+                }
+            }
+        }
+        match self.style.line_endings {
+            LineEnding::Crlf => Formatter::crlf(&self.buf, &self.verbatim),
+            _ => self.buf,
+        }
     }
 
     fn run(&mut self, ast: Ast) {
@@ -40,20 +72,41 @@ impl Formatter {
                 self.write(&data);
                 self.newline();
             }
+            // back on the line of the command before it
+            Ast::TrailingComment(data) => {
+                if self.buf.ends_with(b"\n") {
+                    self.buf.pop();
+                }
+                self.write(b" ;#");
+                self.write(&data);
+                self.newline();
+            }
             Ast::Procedure {
                 name,
                 parameters,
                 body,
+                ..
             } => {
                 self.indent();
                 self.write(b"proc ");
                 self.write(&name);
                 self.write(b" {");
-                for p in parameters {
+                for p in parameters.iter() {
                     self.write(b" ");
-                    self.write(&p);
+                    self.write(p);
                 }
-                self.writeline(b" } {");
+                match parameters.is_empty() {
+                    true => self.writeline(b"} {"),
+                    false => self.writeline(b" } {"),
+                }
+                self.run_nested(*body);
+                self.close_block();
+            }
+            Ast::Namespace { name, body } => {
+                self.indent();
+                self.write(b"namespace eval ");
+                self.write(&name);
+                self.writeline(b" {");
                 self.run_nested(*body);
                 self.close_block();
             }
@@ -61,40 +114,145 @@ impl Formatter {
                 condition_body_clauses: condition_block_vec,
                 maybe_block_if_false,
             } => {
+                self.indent();
                 for (idx, (condition, block)) in condition_block_vec.into_iter().enumerate() {
-                    self.indent();
                     if idx == 0 {
-                        self.write(b"if { ");
+                        self.write(b"if ");
                     } else {
-                        self.write(b"elseif { ");
+                        self.write(b" elseif ");
                     }
-                    self.write(&condition);
-                    self.writeline(b" } {");
+                    self.write_word(condition); // only a braced condition is rebraced
+                    self.writeline(b" {");
                     self.run_nested(block);
-                    self.close_block();
+                    self.close_clause();
                 }
                 if let Some(block_if_false) = maybe_block_if_false {
-                    self.indent();
-                    self.writeline(b"else {");
+                    self.writeline(b" else {");
                     self.run_nested(*block_if_false);
-                    self.close_block();
+                    self.close_clause();
+                }
+                self.newline();
+            }
+            Ast::Catch {
+                body,
+                maybe_result_variable,
+                maybe_options_variable,
+            } => {
+                self.indent();
+                self.writeline(b"catch {");
+                self.run_nested(*body);
+                self.close_clause();
+                for variable in [maybe_result_variable, maybe_options_variable]
+                    .into_iter()
+                    .flatten()
+                {
+                    self.write(b" ");
+                    self.write(&variable);
+                }
+                self.newline();
+            }
+            Ast::Try {
+                body,
+                handlers,
+                maybe_finally,
+            } => {
+                self.indent();
+                self.writeline(b"try {");
+                self.run_nested(*body);
+                self.close_clause();
+                for handler in handlers {
+                    let (keyword, pattern, variables, maybe_body) = match handler {
+                        TryHandler::On {
+                            code,
+                            variables,
+                            maybe_body,
+                        } => (b"on".as_slice(), code, variables, maybe_body),
+                        TryHandler::Trap {
+                            pattern,
+                            variables,
+                            maybe_body,
+                        } => (
+                            b"trap".as_slice(),
+                            Formatter::list(&pattern),
+                            variables,
+                            maybe_body,
+                        ),
+                    };
+                    self.write(b" ");
+                    self.write(keyword);
+                    self.write(b" ");
+                    self.write(&pattern);
+                    self.write(b" ");
+                    self.write(&Formatter::list(&variables));
+                    match maybe_body {
+                        Some(body) => {
+                            self.writeline(b" {");
+                            self.run_nested(body);
+                            self.close_clause();
+                        }
+                        None => self.write(b" -"),
+                    }
+                }
+                if let Some(finally) = maybe_finally {
+                    self.writeline(b" finally {");
+                    self.run_nested(*finally);
+                    self.close_clause();
                 }
+                self.newline();
             }
+            // options in canonical order, only the last of -exact, -glob and -regexp counts in Tcl
             Ast::Switch {
-                condition,
-                value_block_or_fallthrough_vec,
+                options,
+                subject,
+                arms,
             } => {
-                // TODO: sort conditions of fallthrough blocks
                 self.indent();
                 self.write(b"switch ");
-                self.write(&condition);
+                let is_mode = |o: &&SwitchOption| {
+                    matches!(
+                        o,
+                        SwitchOption::Exact | SwitchOption::Glob | SwitchOption::Regexp
+                    )
+                };
+                let options = (options.iter().rfind(is_mode).into_iter())
+                    .chain(options.iter().find(|&&o| o == SwitchOption::NoCase))
+                    .chain(options.iter().find(|&&o| o == SwitchOption::EndOfOptions));
+                for option in options {
+                    self.write(match option {
+                        SwitchOption::Exact => b"-exact ",
+                        SwitchOption::Glob => b"-glob ",
+                        SwitchOption::Regexp => b"-regexp ",
+                        SwitchOption::NoCase => b"-nocase ",
+                        SwitchOption::EndOfOptions => b"-- ",
+                    });
+                }
+                self.write_word(*subject);
                 self.writeline(b" {");
 
                 self.depth += 1;
-                for (value, block_or_fallthrough) in value_block_or_fallthrough_vec {
+                for arm in arms {
+                    let (pattern, maybe_body) = match arm {
+                        SwitchArm::Pattern {
+                            pattern,
+                            maybe_body,
+                        } => (pattern, maybe_body),
+                        // as read, Tcl reads its words as patterns and bodies
+                        SwitchArm::Comment(data) => {
+                            self.indent();
+                            self.write(b"#");
+                            self.write(&data);
+                            self.newline();
+                            continue;
+                        }
+                        SwitchArm::Verbatim(data) => {
+                            self.write_verbatim(&data);
+                            continue;
+                        }
+                    };
                     self.indent();
-                    self.write(&value);
-                    match block_or_fallthrough {
+                    let pattern = Formatter::restyle_pattern(pattern, self.style.switch_patterns);
+                    self.write_word(pattern);
+                    match maybe_body {
                         Some(block) => {
                             self.writeline(b" {");
                             self.run_nested(block);
@@ -108,19 +266,74 @@ impl Formatter {
                 self.depth -= 1;
                 self.close_block();
             }
+            Ast::Command {
+                name,
+                maybe_subcommand,
+                words,
+            } => {
+                self.indent();
+                self.write(&name);
+                if let Some(subcommand) = maybe_subcommand {
+                    self.write(b" ");
+                    self.write(&subcommand);
+                }
+                // the last script is the body, any script before it is written inline if it can be
+                let body_idx = words.iter().rposition(|w| matches!(w, Word::Script(_)));
+                for (idx, word) in words.into_iter().enumerate() {
+                    self.write(b" ");
+                    match word {
+                        Word::Script(script)
+                            if Some(idx) != body_idx && Formatter::is_inline(&script) =>
+                        {
+                            let mut formatter = self.sub_formatter();
+                            formatter.run(script);
+                            self.write(b"{ ");
+                            self.write(formatter.buf.trim_ascii());
+                            self.write(b" }");
+                        }
+                        word => self.write_word(word),
+                    }
+                }
+                self.newline();
+            }
+            Ast::Gtp(command) => {
+                self.indent();
+                self.write_gtp(*command);
+                self.newline();
+            }
+            Ast::Udp(command) => {
+                self.indent();
+                self.write_udp(*command);
+                self.newline();
+            }
             Ast::Statement(s) => {
                 self.indent();
                 self.write_statement(s);
             }
+            // written as it was read, indentation included
+            Ast::Verbatim(data) => self.write_verbatim(&data),
             Ast::EmptyLine => {
                 if self.consecutive_empty_lines <= 2 {
                     self.newline();
                 }
-            },
-            Ast::When { event_name, body } => {
+            }
+            Ast::When {
+                event_name,
+                maybe_priority,
+                maybe_timing,
+                body,
+            } => {
                 self.indent();
                 self.write(b"when ");
                 self.write(&event_name);
+                match maybe_timing {
+                    Some(true) => self.write(b" timing on"),
+                    Some(false) => self.write(b" timing off"),
+                    None => {}
+                }
+                if let Some(priority) = maybe_priority {
+                    self.write(format!(" priority {}", priority).as_bytes());
+                }
                 self.writeline(b" {");
                 self.run_nested(*body);
                 self.close_block();
@@ -128,41 +341,403 @@ impl Formatter {
         }
     }
 
+    // indentation and line endings included
+    fn write_verbatim(&mut self, data: &[u8]) {
+        let start = self.buf.len();
+        self.write(data);
+        self.verbatim.push(start..self.buf.len());
+        if !data.ends_with(b"\n") {
+            self.newline(); // at the end of a file without a final newline
+        }
+    }
+
     fn run_nested(&mut self, ast: Ast) {
         self.depth += 1;
         self.run(ast);
         self.depth -= 1;
     }
 
-    fn write_statement(&mut self, s: Statement) {
-        let (keyword, v1, v2) = match s {
-            Statement::Set { identifier, value } => (b"set".to_vec(), Some(identifier), Some(value)),
-            Statement::Log { bucket, value } => (b"log".to_vec(), Some(bucket), Some(value)),
-            Statement::Snat { ip_address, port } => (b"snat".to_vec(), Some(ip_address), Some(port)),
-            Statement::Node { ip_address, port } => (b"node".to_vec(), Some(ip_address), Some(port)),
-            Statement::Pool { identifier } => (b"pool".to_vec(), Some(identifier), None),
-            Statement::SnatPool { identifier } => (b"snatpool".to_vec(), Some(identifier), None),
-            Statement::Return { value } => (b"return".to_vec(), value, None),
-            Statement::Other { data } => (data, None, None),
+    fn write_gtp(&mut self, command: GtpCommand) {
+        let literal = |text: &[u8]| Word::Bare(vec![WordPart::Text(text.to_vec())]);
+        let (name, words): (&[u8], Vec<Word>) = match command {
+            GtpCommand::Header { arguments } => {
+                (b"GTP::header", Formatter::gtp_arguments(arguments))
+            }
+            GtpCommand::Ie { action, arguments } => {
+                let action: &[u8] = match action {
+                    GtpIeAction::Get => b"get",
+                    GtpIeAction::Count => b"count",
+                    GtpIeAction::Insert => b"insert",
+                    GtpIeAction::Remove => b"remove",
+                    GtpIeAction::Set => b"set",
+                };
+                let mut words = vec![literal(action)];
+                words.extend(Formatter::gtp_arguments(arguments));
+                (b"GTP::ie", words)
+            }
+            GtpCommand::Parse {
+                payload,
+                maybe_length,
+            } => (
+                b"GTP::parse",
+                [Some(payload), maybe_length]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            ),
+            GtpCommand::New {
+                version,
+                message_type,
+            } => (b"GTP::new", vec![version, message_type]),
+            GtpCommand::Respond { message } => (b"GTP::respond", vec![message]),
+            GtpCommand::Forward { message } => (b"GTP::forward", vec![message]),
         };
-        self.write(&keyword);
-        match (v1, v2) {
-            (Some(v1), Some(v2)) => {
-                self.write(b" ");
-                self.write(&v1);
-                self.write(b" ");
-                self.write(&v2);
+        self.write(name);
+        for word in words {
+            self.write(b" ");
+            self.write_word(word);
+        }
+    }
+
+    // in the order given, options may sit between the words they select
+    fn gtp_arguments(arguments: Vec<GtpArgument>) -> Vec<Word> {
+        arguments
+            .into_iter()
+            .flat_map(|argument| match argument {
+                GtpArgument::Option((name, value)) => {
+                    vec![Word::Bare(vec![WordPart::Text(name)]), value]
+                }
+                GtpArgument::Word(word) => vec![word],
+            })
+            .collect()
+    }
+
+    fn write_udp(&mut self, command: UdpCommand) {
+        let (name, words): (&[u8], Vec<Word>) = match command {
+            UdpCommand::Payload { arguments } => (b"UDP::payload", arguments),
+            UdpCommand::PayloadReplace {
+                offset,
+                length,
+                data,
+            } => {
+                let replace = Word::Bare(vec![WordPart::Text(b"replace".to_vec())]);
+                (b"UDP::payload", vec![replace, offset, length, data])
             }
-            (Some(v1), None) => {
+            UdpCommand::Respond { data } => (b"UDP::respond", vec![data]),
+            UdpCommand::Drop => (b"UDP::drop", Vec::new()),
+        };
+        self.write(name);
+        for word in words {
+            self.write(b" ");
+            self.write_word(word);
+        }
+    }
+
+    fn write_statement(&mut self, s: Statement) {
+        match s {
+            Statement::Set {
+                target,
+                maybe_value,
+            } => {
+                self.write(b"set ");
+                self.write_variable_name(target);
+                match maybe_value.map(|value| *value) {
+                    // the lines of a braced value are indented relative to the set
+                    Some(Word::Braced(data)) => {
+                        self.write(b" {");
+                        self.write_multiline(&data);
+                        self.write(b"}");
+                    }
+                    Some(value) => {
+                        self.write(b" ");
+                        self.write_word(value);
+                    }
+                    None => {}
+                }
+            }
+            Statement::Log {
+                flags,
+                maybe_destination,
+                maybe_facility,
+                message,
+            } => {
+                self.write(b"log");
+                for data in flags
+                    .iter()
+                    .chain(&maybe_destination)
+                    .chain(&maybe_facility)
+                {
+                    self.write(b" ");
+                    self.write(data);
+                }
                 self.write(b" ");
-                self.write(&v1);
+                self.write_word(*message);
+            }
+            Statement::Snat { target } => {
+                self.write(b"snat ");
+                match target {
+                    SnatTarget::Automap => self.write(b"automap"),
+                    SnatTarget::None => self.write(b"none"),
+                    SnatTarget::Endpoint(endpoint) => self.write_endpoint(*endpoint),
+                }
+            }
+            Statement::Node { endpoint } => {
+                self.write(b"node ");
+                self.write_endpoint(*endpoint);
+            }
+            Statement::Pool { name, maybe_member } => {
+                self.write_pool(b"pool ", *name, maybe_member)
+            }
+            Statement::SnatPool { name, maybe_member } => {
+                self.write_pool(b"snatpool ", *name, maybe_member)
+            }
+            Statement::Return {
+                options,
+                maybe_value,
+            } => {
+                self.write(b"return");
+                for (name, value) in options {
+                    self.write(b" ");
+                    self.write(&name);
+                    self.write(b" ");
+                    self.write_word(value);
+                }
+                if let Some(value) = maybe_value {
+                    self.write(b" ");
+                    self.write_word(*value);
+                }
             }
-            (None, None) => {},
-            _ => unreachable!(),
         }
         self.newline();
     }
 
+    fn write_pool(&mut self, keyword: &[u8], name: Word, maybe_member: Option<Box<Endpoint>>) {
+        self.write(keyword);
+        self.write_word(name);
+        if let Some(member) = maybe_member {
+            self.write(b" member ");
+            self.write_endpoint(*member);
+        }
+    }
+
+    fn write_endpoint(&mut self, endpoint: Endpoint) {
+        match endpoint.address {
+            Address::Literal {
+                ip,
+                maybe_route_domain,
+                maybe_port,
+            } => {
+                self.write(&ip);
+                if let Some(route_domain) = maybe_route_domain {
+                    self.write(b"%");
+                    self.write(&route_domain);
+                }
+                if let Some(port) = maybe_port {
+                    // IPv6 takes a dot before the port
+                    self.write(if ip.contains(&b':') { b"." } else { b":" });
+                    self.write(&port);
+                }
+            }
+            Address::Word(word) => self.write_word(*word),
+        }
+        if let Some(port) = endpoint.maybe_port {
+            self.write(b" ");
+            self.write_word(*port);
+        }
+    }
+
+    fn write_word(&mut self, word: Word) {
+        match word {
+            Word::Bare(parts) => self.write_word_parts(parts),
+            Word::Quoted(parts) => {
+                self.write(b"\"");
+                self.write_word_parts(parts);
+                self.write(b"\"");
+            }
+            // as read, the text between braces is the value
+            Word::Braced(data) => {
+                self.write(b"{");
+                self.write(&data);
+                self.write(b"}");
+            }
+            Word::Script(script) => {
+                self.writeline(b"{");
+                self.run_nested(script);
+                self.close_clause();
+            }
+            Word::Expression(expression) => {
+                self.write(b"{ ");
+                self.write(&expression);
+                self.write(b" }");
+            }
+            Word::List(elements) => self.write_list(elements),
+            Word::CommandSubstitution(body) => self.write_substitution(body),
+            Word::Variable(variable) => self.write_variable(variable),
+            Word::Expansion(word) => {
+                self.write(b"{*}");
+                self.write_word(*word);
+            }
+        }
+    }
+
+    // a pattern is only requoted when both spellings are the same literal text
+    fn restyle_pattern(word: Word, style: PatternStyle) -> Word {
+        let text = match &word {
+            Word::Bare(parts) | Word::Quoted(parts) => match parts.as_slice() {
+                [] => Vec::new(),
+                [WordPart::Text(text)] => text.clone(),
+                _ => return word,
+            },
+            Word::Braced(text) => text.clone(),
+            _ => return word,
+        };
+        if text == b"default" || text.iter().any(|c| b"\\$[]{}\"".contains(c)) {
+            return word;
+        }
+        match (style, word) {
+            (PatternStyle::Quoted, _) => Word::Quoted(vec![WordPart::Text(text)]),
+            (PatternStyle::Braced, _) => Word::Braced(text),
+            (PatternStyle::Preserve, word) => word,
+        }
+    }
+
+    fn write_list(&mut self, elements: Vec<Vec<u8>>) {
+        let inline = elements.join(&b' ');
+        let column = self.buf.len()
+            - self
+                .buf
+                .iter()
+                .rposition(|&c| c == b'\n')
+                .map_or(0, |i| i + 1);
+        if column + inline.len() + 2 <= self.style.max_width && !inline.contains(&b'\n') {
+            self.write(b"{");
+            self.write(&inline);
+            self.write(b"}");
+            return;
+        }
+        self.writeline(b"{");
+        self.depth += 1;
+        for element in elements {
+            self.indent();
+            self.write(&element);
+            self.newline();
+        }
+        self.depth -= 1;
+        self.indent();
+        self.write(b"}");
+    }
+
+    fn write_word_parts(&mut self, parts: Vec<WordPart>) {
+        for part in parts {
+            match part {
+                WordPart::Text(data) => self.write_text(&data),
+                WordPart::Variable(variable) => self.write_variable(variable),
+                WordPart::CommandSubstitution(body) => self.write_substitution(body),
+            }
+        }
+    }
+
+    fn write_variable(&mut self, variable: Variable) {
+        self.write(b"$");
+        self.write_variable_name(variable);
+    }
+
+    fn write_variable_name(&mut self, variable: Variable) {
+        if variable.is_braced {
+            self.write(b"{");
+        }
+        if let Some(namespace) = variable.maybe_namespace {
+            self.write(&namespace);
+            self.write(b"::");
+        }
+        self.write(&variable.name);
+        if variable.is_braced {
+            self.write(b"}");
+        }
+        if let Some(index) = variable.maybe_index {
+            self.write(b"(");
+            self.write_word_parts(index);
+            self.write(b")");
+        }
+    }
+
+    // a single command is written inside the brackets, any script nested in it is indented
+    // relative to the current line, more commands go on their own lines like a block
+    fn write_substitution(&mut self, body: Ast) {
+        match body {
+            Ast::Block(mut trees) if trees.len() == 1 => {
+                let mut formatter = self.sub_formatter();
+                formatter.depth = self.depth;
+                formatter.run(trees.remove(0));
+                self.write(b"[");
+                self.write(formatter.buf.trim_ascii());
+                self.write(b"]");
+            }
+            Ast::Block(trees) if trees.is_empty() => self.write(b"[]"),
+            body => {
+                self.writeline(b"[");
+                self.run_nested(body);
+                self.indent();
+                self.write(b"]");
+            }
+        }
+    }
+
+    // lines after the first are indented one level deeper, keeping their indentation relative
+    // to each other, except for an empty last line, which is where the closing bracket goes
+    fn write_multiline(&mut self, data: &[u8]) {
+        let indent_len = |line: &[u8]| line.len() - line.trim_ascii_start().len();
+        let common = (data.split(|&c| c == b'\n').skip(1))
+            .filter(|line| !line.trim_ascii().is_empty())
+            .map(indent_len)
+            .min()
+            .unwrap_or(0);
+        let mut lines = data.split(|&c| c == b'\n').peekable();
+        if let Some(first) = lines.next() {
+            self.write(first);
+        }
+        while let Some(line) = lines.next() {
+            self.newline();
+            let line = &line[common.min(indent_len(line))..];
+            let line = match line.trim_ascii().is_empty() {
+                true => &line[line.len()..],
+                false => line,
+            };
+            match (line.is_empty(), lines.peek()) {
+                (true, None) => self.indent(),
+                (true, Some(_)) => {}
+                (false, _) => {
+                    self.depth += 1;
+                    self.indent();
+                    self.depth -= 1;
+                    self.write(line);
+                }
+            }
+        }
+    }
+
+    // a newline is part of the text, only the line after a line continuation is reindented,
+    // Tcl replaces it and the spaces around it with a single space
+    fn write_text(&mut self, data: &[u8]) {
+        let mut lines = data.split(|&c| c == b'\n');
+        let mut previous = lines.next().unwrap_or_default();
+        self.write(previous);
+        for line in lines {
+            self.newline();
+            let backslashes = previous.iter().rev().take_while(|&&c| c == b'\\').count();
+            match backslashes % 2 {
+                1 => {
+                    self.depth += 1;
+                    self.indent();
+                    self.depth -= 1;
+                    self.write(line.trim_ascii_start());
+                }
+                _ => self.write(line),
+            }
+            previous = line;
+        }
+    }
+
     fn write(&mut self, slice: &[u8]) {
         self.buf.extend_from_slice(slice);
     }
@@ -181,13 +756,51 @@ impl Formatter {
         self.write(b"}\n");
     }
 
+    // closes a block that may be followed by another clause on the same line, i.e. `} else {`
+    fn close_clause(&mut self) {
+        self.indent();
+        self.write(b"}");
+    }
+
+    // a script of a single command without nested scripts fits on one line
+    fn is_inline(ast: &Ast) -> bool {
+        match ast {
+            Ast::Block(trees) => matches!(trees.as_slice(), [tree] if Formatter::is_inline(tree)),
+            Ast::Statement(_) => true,
+            Ast::Command { words, .. } => !words.iter().any(|w| matches!(w, Word::Script(_))),
+            _ => false,
+        }
+    }
+
+    fn crlf(buf: &[u8], verbatim: &[Range<usize>]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(buf.len() + buf.len() / 32);
+        for (idx, &c) in buf.iter().enumerate() {
+            if c == b'\n' && !verbatim.iter().any(|range| range.contains(&idx)) {
+                out.push(b'\r');
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    // a single-element list is written bare, everything else is wrapped in brackets
+    fn list(elements: &[Vec<u8>]) -> Vec<u8> {
+        match elements {
+            [element] => element.to_vec(),
+            _ => {
+                let mut buf = b"{".to_vec();
+                buf.extend(elements.join(&b' '));
+                buf.push(b'}');
+                buf
+            }
+        }
+    }
+
     fn indent(&mut self) {
-        let data = iter::repeat(b"    ")
-            .take(self.depth)
-            .fold(Vec::new(), |mut acc, e| {
-                acc.extend_from_slice(e);
-                acc
-            });
+        let data = std::iter::repeat_n(b"    ", self.depth).fold(Vec::new(), |mut acc, e| {
+            acc.extend_from_slice(e);
+            acc
+        });
         self.buf.extend_from_slice(&data);
     }
 }
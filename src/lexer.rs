@@ -1,5 +1,4 @@
-use std::io::BufRead;
-
+#[derive(Clone)]
 pub enum Token {
     KeywordSet,          // set
     KeywordProc,         // proc
@@ -14,6 +13,8 @@ pub enum Token {
     KeywordSnatPool,     // snatpool
     KeywordReturn,       // return
     KeywordWhen,         // when
+    KeywordCatch,        // catch
+    KeywordTry,          // try
     DoubleColon,         // ::
     Colon,               // :
     LCurlyBracket,       // {
@@ -34,69 +35,80 @@ pub enum Token {
     Star,                // *
     Bang,                // !
     Ampersand,           // &
+    Expansion,           // {*} at the start of a word
     Newline,             // \n
+    Verbatim(Vec<u8>),   // lines protected by a `# tclfmt: off` or `# tclfmt: skip` comment
+    Semicolon,           // ;
+    Space(Vec<u8>),      // [\s\t]+ between two tokens of a line
     Identifier(Vec<u8>), // [a-zA-Z0-9_\.]+
     Other(Vec<u8>),      // <lazy>
 }
 
 impl Token {
-    fn is_keyword(&self) -> bool {
-        match self {
+    pub fn is_keyword(&self) -> bool {
+        matches!(
+            self,
             Token::KeywordSet
-            | Token::KeywordProc
-            | Token::KeywordIf
-            | Token::KeywordElseIf
-            | Token::KeywordElse
-            | Token::KeywordSwitch
-            | Token::KeywordLog
-            | Token::KeywordSnat
-            | Token::KeywordNode
-            | Token::KeywordPool
-            | Token::KeywordSnatPool
-            | Token::KeywordReturn
-            | Token::KeywordWhen => true,
-            _ => false,
-        }
+                | Token::KeywordProc
+                | Token::KeywordIf
+                | Token::KeywordElseIf
+                | Token::KeywordElse
+                | Token::KeywordSwitch
+                | Token::KeywordLog
+                | Token::KeywordSnat
+                | Token::KeywordNode
+                | Token::KeywordPool
+                | Token::KeywordSnatPool
+                | Token::KeywordReturn
+                | Token::KeywordWhen
+                | Token::KeywordCatch
+                | Token::KeywordTry
+        )
     }
 
     fn is_operator(&self) -> bool {
-        match self {
+        matches!(
+            self,
             Token::DoubleColon
-            | Token::Minus
-            | Token::Plus
-            | Token::Equals
-            | Token::Modulo
-            | Token::Star
-            | Token::Ampersand => true,
-            _ => false,
-        }
+                | Token::Minus
+                | Token::Plus
+                | Token::Equals
+                | Token::Modulo
+                | Token::Star
+                | Token::Ampersand
+        )
     }
 
     fn is_symbol(&self) -> bool {
-        match self {
-            Token::Colon | Token::Dollar | Token::Quote | Token::Bang => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            Token::Colon
+                | Token::Dollar
+                | Token::Quote
+                | Token::Bang
+                | Token::Expansion
+                | Token::Semicolon
+        )
     }
 
     fn is_bracket(&self) -> bool {
-        match self {
+        matches!(
+            self,
             Token::LCurlyBracket
-            | Token::RCurlyBracket
-            | Token::LSquareBracket
-            | Token::RSquareBracket
-            | Token::LParen
-            | Token::RParen
-            | Token::LAngleBracket
-            | Token::RAngleBracket => true,
-            _ => false,
-        }
+                | Token::RCurlyBracket
+                | Token::LSquareBracket
+                | Token::RSquareBracket
+                | Token::LParen
+                | Token::RParen
+                | Token::LAngleBracket
+                | Token::RAngleBracket
+        )
     }
 }
 
 #[derive(Debug)]
 pub enum LexerFail {
-    ExpectedString, // when parsing log
+    UnexpectedInput, // bytes that no token starts with
 }
 
 type Result<T> = std::result::Result<T, LexerFail>;
@@ -110,40 +122,149 @@ impl Lexer {
         Self { tokens: Vec::new() }
     }
 
+    // works on raw bytes, anything that isn't UTF-8 is passed through unchanged
     pub fn lex(mut self, buf: Vec<u8>) -> Result<Vec<Token>> {
-        for line in buf.lines() {
-            self.lex_line(&Lexer::normalize(line.unwrap().as_bytes()))?; // lstrip & rstrip
+        let mut lines = Lexer::lines(&buf).peekable();
+        while let Some(line) = lines.next() {
+            self.lex_line(line.strip_suffix(b"\r").unwrap_or(line))?;
             self.tokens.push(Token::Newline);
+
+            // a region ends before a line that closes a block opened before it
+            let mut region: Vec<&[u8]> = Vec::new();
+            let mut depth = 0;
+            let mut is_inside = |line: &[u8]| {
+                let (lowest, end) = Lexer::brace_balance(line);
+                let is_inside = depth + lowest >= 0;
+                depth += end;
+                is_inside
+            };
+            match Lexer::directive(line) {
+                // up to the next `# tclfmt: on`, the end of the block or the end of the file
+                Some(b"off") => {
+                    while let Some(line) =
+                        lines.next_if(|l| Lexer::directive(l) != Some(b"on") && is_inside(l))
+                    {
+                        region.push(line);
+                    }
+                }
+                // the lines of the next command
+                Some(b"skip") => {
+                    while let Some(line) = lines.next_if(|l| is_inside(l)) {
+                        region.push(line);
+                        let text = region.join(b"\n".as_slice());
+                        if !text.trim_ascii().is_empty() && Lexer::is_complete(&text) {
+                            break;
+                        }
+                    }
+                }
+                _ => continue,
+            }
+            // with the line endings as read, the last one included
+            if !region.is_empty() {
+                let mut data = region.join(b"\n".as_slice());
+                if lines.peek().is_some() || buf.ends_with(b"\n") {
+                    data.push(b'\n');
+                }
+                self.tokens.push(Token::Verbatim(data));
+                self.tokens.push(Token::Newline);
+            }
         }
 
         Ok(self.tokens)
     }
 
-    fn lex_line(&mut self, mut line: &[u8]) -> Result<()> {
-        while let Some((token, consumed)) = self.try_lex(line) {
-            self.tokens.push(token);
-            line = &line[consumed..];
+    // the `off` of a `# tclfmt: off` line
+    fn directive(line: &[u8]) -> Option<&[u8]> {
+        let text = line.trim_ascii().strip_prefix(b"#")?.trim_ascii();
+        text.strip_prefix(b"tclfmt:").map(|x| x.trim_ascii())
+    }
 
-            let n = self.tokens.len().saturating_sub(1);
-            match (self.tokens.get(n.saturating_sub(1)), self.tokens.get(n)) {
-                (_, Some(Token::Hash)) => {
-                    self.tokens.push(Token::Other(Lexer::normalize(&line))); // lstrip only
-                    return Ok(());
-                }
-                (Some(Token::KeywordLog), Some(Token::Identifier(_))) => {
-                    let (value, consumed) =
-                        Lexer::extract_string(line).ok_or(LexerFail::ExpectedString)?;
-                    self.tokens.push(Token::Other(value.to_vec()));
-                    line = &line[consumed..];
+    // like Tcl's `info complete`, brackets and quotes are closed and the last line isn't continued
+    fn is_complete(text: &[u8]) -> bool {
+        let mut braces = 0usize;
+        let mut brackets = 0usize;
+        let mut is_quoted = false;
+        let mut is_escaped = false;
+        for &c in text {
+            match c {
+                _ if is_escaped => is_escaped = false,
+                b'\\' => is_escaped = true,
+                b'{' if !is_quoted => braces += 1,
+                b'}' if !is_quoted => braces = braces.saturating_sub(1),
+                b'"' if braces == 0 => is_quoted = !is_quoted,
+                b'[' if braces == 0 => brackets += 1,
+                b']' if braces == 0 => brackets = brackets.saturating_sub(1),
+                _ => {}
+            }
+        }
+        braces == 0 && brackets == 0 && !is_quoted && !is_escaped
+    }
+
+    // (lowest, last) depth of braces along the line, relative to its start, quotes aren't looked at
+    fn brace_balance(line: &[u8]) -> (isize, isize) {
+        let (mut lowest, mut depth) = (0, 0);
+        let mut is_escaped = false;
+        for &c in line {
+            match c {
+                _ if is_escaped => is_escaped = false,
+                b'\\' => is_escaped = true,
+                b'{' => depth += 1,
+                b'}' => {
+                    depth -= 1;
+                    lowest = lowest.min(depth);
                 }
                 _ => {}
             }
         }
+        (lowest, depth)
+    }
+
+    // the numbers of the lines that aren't valid UTF-8, starting at 1
+    pub fn invalid_utf8_lines(buf: &[u8]) -> Vec<usize> {
+        Lexer::lines(buf)
+            .enumerate()
+            .filter(|(_, line)| std::str::from_utf8(line).is_err())
+            .map(|(idx, _)| idx + 1)
+            .collect()
+    }
+
+    // like BufRead::lines, with a trailing \r and without an empty line after the last newline
+    fn lines(buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+        let buf = buf.strip_suffix(b"\n").unwrap_or(buf);
+        buf.split(|&c| c == b'\n').filter(move |_| !buf.is_empty())
+    }
+
+    // the indentation is kept, braced words are written back as they were read
+    fn lex_line(&mut self, line: &[u8]) -> Result<()> {
+        let indent = line
+            .iter()
+            .take_while(|&&x| matches!(x, b' ' | b'\t'))
+            .count();
+        let (indent, mut line) = line.split_at(indent);
+        if line.is_empty() {
+            return Ok(()); // a blank line, to be told apart as an empty line
+        }
+        if !indent.is_empty() {
+            self.tokens.push(Token::Space(indent.to_vec()));
+        }
+
+        // a hash only starts a comment at the beginning of a command
+        if let Some(comment_text) = line.strip_prefix(b"#") {
+            self.tokens.push(Token::Hash);
+            self.tokens.push(Token::Other(comment_text.to_vec())); // as written
+            return Ok(());
+        }
+
+        // a hash after a semicolon is left to the parser, which knows whether it's inside a word
+        while let Some((token, consumed)) = self.try_lex(line) {
+            self.tokens.push(token);
+            line = &line[consumed..];
+        }
         if !line.is_empty() {
             println!("lexer: failed to read next");
             dbg!(self.tokens.last());
             dbg!(String::from_utf8_lossy(line));
-            unreachable!();
+            return Err(LexerFail::UnexpectedInput);
         }
         Ok(())
     }
@@ -151,24 +272,24 @@ impl Lexer {
     fn try_lex(&self, line: &[u8]) -> Option<(Token, usize)> {
         let consumed = line
             .iter()
-            .take_while(|&&x| is_whitespace_or_semicolon(x))
+            .take_while(|&&x| matches!(x, b' ' | b'\t'))
             .count();
 
-        match &line[consumed..] {
-            x if x.starts_with(b"snatpool") => Some((Token::KeywordSnatPool, 8)),
-            x if x.starts_with(b"switch") => Some((Token::KeywordSwitch, 6)),
-            x if x.starts_with(b"return") => Some((Token::KeywordReturn, 6)),
-            x if x.starts_with(b"elseif") => Some((Token::KeywordElseIf, 6)),
-            x if x.starts_with(b"else") => Some((Token::KeywordElse, 4)),
-            x if x.starts_with(b"pool ") => Some((Token::KeywordPool, 4)),
-            x if x.starts_with(b"node ") => Some((Token::KeywordNode, 4)),
-            x if x.starts_with(b"proc ") => Some((Token::KeywordProc, 4)),
-            x if x.starts_with(b"snat ") => Some((Token::KeywordSnat, 4)),
-            x if x.starts_with(b"when ") => Some((Token::KeywordWhen, 4)),
-            x if x.starts_with(b"log ") => Some((Token::KeywordLog, 3)),
-            x if x.starts_with(b"set ") => Some((Token::KeywordSet, 3)),
-            x if x.starts_with(b"if") => Some((Token::KeywordIf, 2)),
+        if consumed > 0 {
+            return Some((Token::Space(line[..consumed].to_vec()), consumed));
+        }
+
+        // only a prefix of a word, `{*}` on its own is a braced star
+        let is_expansion = matches!(
+            self.tokens.last(),
+            None | Some(
+                Token::Space(_) | Token::Newline | Token::LSquareBracket | Token::Semicolon
+            )
+        ) && line.get(3).is_some_and(|&c| !is_whitespace_or_semicolon(c));
+        match line {
+            x if x.starts_with(b"{*}") && is_expansion => Some((Token::Expansion, 3)),
             x if x.starts_with(b"::") => Some((Token::DoubleColon, 2)),
+            x if x.starts_with(b";") => Some((Token::Semicolon, 1)),
             x if x.starts_with(b"[") => Some((Token::LSquareBracket, 1)),
             x if x.starts_with(b"]") => Some((Token::RSquareBracket, 1)),
             x if x.starts_with(b"{") => Some((Token::LCurlyBracket, 1)),
@@ -188,63 +309,59 @@ impl Lexer {
             x if x.starts_with(b"!") => Some((Token::Bang, 1)),
             x if x.starts_with(b"&") => Some((Token::Ampersand, 1)),
             x if x.starts_with(b":") => Some((Token::Colon, 1)),
+            // an escaped character, or a line continuation if nothing follows
+            x if x.starts_with(b"\\") => {
+                let len = x.len().min(2);
+                Some((Token::Other(x[..len].to_vec()), len))
+            }
             b"" => None,
             x => {
                 let identifier = Lexer::extract_identifier(x);
                 let len = identifier.len();
                 if len == 0 {
-                    None
+                    // anything else is passed through, a multi-byte character in one piece
+                    let len = match x[0] {
+                        c if c >= 0x80 => x.iter().take_while(|&&c| c >= 0x80).count(),
+                        _ => 1,
+                    };
+                    Some((Token::Other(x[..len].to_vec()), len))
                 } else {
-                    Some((Token::Identifier(identifier), len))
+                    // keywords are whole words only, i.e. `iface` is an identifier
+                    let token = match identifier.as_slice() {
+                        b"set" => Token::KeywordSet,
+                        b"proc" => Token::KeywordProc,
+                        b"if" => Token::KeywordIf,
+                        b"elseif" => Token::KeywordElseIf,
+                        b"else" => Token::KeywordElse,
+                        b"switch" => Token::KeywordSwitch,
+                        b"log" => Token::KeywordLog,
+                        b"snat" => Token::KeywordSnat,
+                        b"node" => Token::KeywordNode,
+                        b"pool" => Token::KeywordPool,
+                        b"snatpool" => Token::KeywordSnatPool,
+                        b"return" => Token::KeywordReturn,
+                        b"when" => Token::KeywordWhen,
+                        b"catch" => Token::KeywordCatch,
+                        b"try" => Token::KeywordTry,
+                        _ => Token::Identifier(identifier),
+                    };
+                    Some((token, len))
                 }
             }
         }
-        .map(|(t, c)| (t, c + consumed))
-    }
-
-    // TODO: normalize = lstrip + rstrip
-    fn normalize(line: &[u8]) -> Vec<u8> {
-        let mut buf: Vec<u8> = line
-            .into_iter()
-            .rev()
-            .skip_while(|&&x| is_whitespace_or_semicolon(x))
-            .copied()
-            .collect();
-        buf.reverse();
-        buf.into_iter()
-            .skip_while(|&x| is_whitespace_or_semicolon(x))
-            .collect()
     }
 
     fn extract_identifier(line: &[u8]) -> Vec<u8> {
         // assume line is lstripped
-        line.into_iter()
+        line.iter()
             .take_while(|&&x| x.is_ascii_alphanumeric() || x == b'_' || x == b'.')
             .cloned()
             .collect()
     }
-
-    fn extract_string(mut data: &[u8]) -> Option<(&[u8], usize)> {
-        let consumed = data
-            .iter()
-            .take_while(|&&x| is_whitespace_or_semicolon(x))
-            .count();
-        data = &data[consumed..];
-
-        let Some(b'"') = data.get(0) else { return None };
-        let inside_len = data.iter().skip(1).take_while(|&&c| c != b'"').count();
-        match data.get(inside_len + 1) {
-            Some(b'"') => Some((&data[..=inside_len + 1], consumed + inside_len + 2)),
-            _ => None,
-        }
-    }
 }
 
 fn is_whitespace_or_semicolon(symbol: u8) -> bool {
-    match symbol {
-        b' ' | b'\t' | b';' => true,
-        _ => false,
-    }
+    matches!(symbol, b' ' | b'\t' | b';')
 }
 
 impl From<&Token> for Vec<u8> {
@@ -263,6 +380,8 @@ impl From<&Token> for Vec<u8> {
             Token::KeywordSnatPool => b"snatpool".to_vec(),
             Token::KeywordReturn => b"return".to_vec(),
             Token::KeywordWhen => b"when".to_vec(),
+            Token::KeywordCatch => b"catch".to_vec(),
+            Token::KeywordTry => b"try".to_vec(),
             Token::DoubleColon => b"::".to_vec(),
             Token::LSquareBracket => b"[".to_vec(),
             Token::RSquareBracket => b"]".to_vec(),
@@ -275,6 +394,7 @@ impl From<&Token> for Vec<u8> {
             Token::Dollar => b"$".to_vec(),
             Token::Hash => b"#".to_vec(),
             Token::Newline => b"\n".to_vec(),
+            Token::Space(data) => data.to_vec(),
             Token::Identifier(data) => data.to_vec(),
             Token::Other(data) => data.to_vec(),
             Token::Quote => b"\"".to_vec(),
@@ -286,26 +406,67 @@ impl From<&Token> for Vec<u8> {
             Token::Bang => b"!".to_vec(),
             Token::Ampersand => b"&".to_vec(),
             Token::Colon => b":".to_vec(),
+            Token::Semicolon => b";".to_vec(),
+            Token::Expansion => b"{*}".to_vec(),
+            Token::Verbatim(data) => data.strip_suffix(b"\n").unwrap_or(data).to_vec(), // the newline token follows
         }
     }
 }
 
 impl std::fmt::Debug for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = String::from_utf8(Vec::from(self)).expect("Failed to utf8 decode");
+        let s = String::from_utf8_lossy(&Vec::from(self)).into_owned();
         match self {
             x if x.is_keyword() => write!(f, "kwrd:  \u{001b}[31m{}\u{001b}[0m", s),
             x if x.is_symbol() => write!(f, "sym:   \u{001b}[32m{}\u{001b}[0m", s),
             x if x.is_operator() => write!(f, "op:    \u{001b}[33m{}\u{001b}[0m", s),
             x if x.is_bracket() => write!(f, "brkt:  \u{001b}[34m{}\u{001b}[0m", s),
             Self::Newline => write!(f, "lf:    \u{001b}[1m\\n\u{001b}[0m"),
+            Self::Space(_) => write!(f, "space: {:?}", s),
             Self::Other(_) => write!(f, "other: \u{001b}[36m{}\u{001b}[0m", s),
             Self::Identifier(_) => write!(f, "ident: {}", s),
             Self::Hash => write!(f, "hash:  \u{001b}[32m{}\u{001b}[0m", s),
-            _ => {
-                println!("{}", s);
-                unreachable!()
-            }
+            Self::Verbatim(data) => write!(f, "verb:  {} bytes", data.len()),
+            _ => unreachable!("{}", s),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verbatim(source: &[u8]) -> Vec<Vec<u8>> {
+        let tokens = Lexer::new()
+            .lex(source.to_vec())
+            .unwrap_or_else(|_| panic!());
+        tokens
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Verbatim(data) => Some(data),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_region_keeps_its_line_endings() {
+        let source = b"set a 1\n# tclfmt: off\nset  x 1\r\nset  y 2\n# tclfmt: on\n";
+        assert_eq!(verbatim(source), [b"set  x 1\r\nset  y 2\n"]);
+        // without a final newline
+        assert_eq!(verbatim(b"# tclfmt: off\r\nset  x 1\r"), [b"set  x 1\r"]);
+    }
+
+    #[test]
+    fn a_region_ends_at_the_close_of_its_block() {
+        let source = b"proc p {} {\n    # tclfmt: off\n    set  x {\n    }\n}\nset  y 1\n";
+        assert_eq!(verbatim(source), [b"    set  x {\n    }\n"]);
+        // `} else {` closes the block it's in first
+        let source = b"if {1} {\n    # tclfmt: off\n    set  x 1\n} else {\n}\n";
+        assert_eq!(verbatim(source), [b"    set  x 1\n"]);
+        // nothing to skip before the closing brace
+        assert!(verbatim(b"proc p {} {\n    # tclfmt: skip\n}\n").is_empty());
+        let source = b"# tclfmt: skip\nset  x {\n  a\n}\nset  y 1\n";
+        assert_eq!(verbatim(source), [b"set  x {\n  a\n}\n"]);
+    }
+}
@@ -1,40 +1,135 @@
 use std::io;
 
 mod ast;
+mod config;
+mod diff;
 mod formatter;
 mod lexer;
+mod normalizer;
 mod parser;
+mod range;
+mod signature;
 
+use config::*;
+use diff::Granularity;
 use formatter::*;
 use lexer::*;
+use normalizer::*;
 use parser::*;
+use range::*;
 
+// tcl-formatter [--lines FIRST:LAST | --bytes START:END] [--edits | --line-edits JSON]
+//               [--cursor OFFSET]... [FILE...]
+// with --edits the files are left as they are and the edits are written to JSON instead
+// each --cursor is a byte offset of the input, its offset in the output is written to the JSON,
+// so --cursor needs --edits or --line-edits
 fn main() -> io::Result<()> {
-    for path in [
-        "../a1-gtp-proxy/src/GTP-C-INIT_V5.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientAcceptV5.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientAcceptv3.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientAcceptv31.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientEgressV5.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientEgressv3.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientIngressV5.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientIngressv3.tcl",
-        "../a1-gtp-proxy/src/GTP-C-clientIngressv31.tcl",
-        "../a1-gtp-proxy/src/GTP-C-variables_V5.tcl",
-        "../a1-gtp-proxy/src/GTP-U_v4.tcl",
-        "../a1-gtp-proxy/src/GTP-Uv3.tcl",
-        "../a1-gtp-proxy/src/GTP-Uv31.tcl",
-        "../a1-gtp-proxy/src/lib_GTPutil_V4.tcl",
-        "../a1-gtp-proxy/src/lib_GTPutil_V5.tcl",
-        "../a1-gtp-proxy/src/lib_GTPutilv3.tcl",
-        "../a1-gtp-proxy/src/lib_LogUtil.tcl",
-    ] {
+    let config = Config::load(".tclfmt").unwrap_or_else(|e| panic!("Failed to load config: {e}"));
+    let mut maybe_selection = None;
+    let mut maybe_edits: Option<(Granularity, String)> = None;
+    let mut cursors: Vec<usize> = Vec::new();
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--lines" | "--bytes" => {
+                let value = args.next().unwrap_or_default();
+                let selection = Selection::parse(&arg, &value)
+                    .unwrap_or_else(|| panic!("Invalid range {arg} {value}"));
+                maybe_selection = Some(selection);
+            }
+            "--edits" | "--line-edits" => {
+                let granularity = match arg.as_str() {
+                    "--edits" => Granularity::Token,
+                    _ => Granularity::Line,
+                };
+                let json_path = args
+                    .next()
+                    .unwrap_or_else(|| panic!("Missing path after {arg}"));
+                maybe_edits = Some((granularity, json_path));
+            }
+            "--cursor" => {
+                let value = args.next().unwrap_or_default();
+                let offset = value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Invalid offset {value}"));
+                cursors.push(offset);
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if !cursors.is_empty() && maybe_edits.is_none() {
+        panic!("--cursor needs --edits or --line-edits");
+    }
+    if paths.is_empty() {
+        paths = DEFAULT_PATHS.iter().map(|path| path.to_string()).collect();
+    }
+
+    let mut files_json = Vec::new();
+    for path in paths {
         println!("formatting {path}");
-        let buf = std::fs::read(path)?;
-        let tokens = Lexer::new().lex(buf).expect("Failed to lex");
-        let ast = Parser::new().parse(&tokens).expect("Failed to parse");
-        let buf = Formatter::new().format(ast); // cursed interface
-        std::fs::write(path, buf)?;
+        let buf = std::fs::read(&path)?;
+        if config.style.warn_encoding {
+            for line in Lexer::invalid_utf8_lines(&buf) {
+                eprintln!("{path}:{line}: not valid UTF-8, passed through unchanged");
+            }
+        }
+        let style = Style {
+            line_endings: config.style.line_endings.resolve(&buf),
+            final_newline: config.style.final_newline.resolve(&buf),
+            ..config.style.clone()
+        };
+        let tokens = Lexer::new().lex(buf.clone()).expect("Failed to lex");
+        let mut ast = Parser::with_signatures(config.signatures.clone())
+            .parse(&tokens)
+            .expect("Failed to parse");
+        for warning in Normalizer::with_style(config.style.clone()).normalize(&mut ast) {
+            eprintln!("{path}: {warning}");
+        }
+        let formatted = Formatter::with_style(style).format(ast); // cursed interface
+        let formatted = match maybe_selection {
+            Some(selection) => format_range(&buf, &formatted, selection),
+            None => formatted,
+        };
+        match &maybe_edits {
+            Some((granularity, _)) => {
+                let edits = diff::edits(&buf, &formatted, *granularity);
+                let moved_cursors: Vec<String> = diff::map_offsets(&buf, &formatted, &cursors)
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect();
+                files_json.push(format!(
+                    "{{\"path\": {}, \"edits\": {}, \"cursors\": [{}]}}",
+                    diff::json_string(&path),
+                    diff::to_json(&edits),
+                    moved_cursors.join(", ")
+                ));
+            }
+            None => std::fs::write(&path, formatted)?,
+        }
+    }
+    if let Some((_, json_path)) = maybe_edits {
+        std::fs::write(json_path, format!("[{}]\n", files_json.join(",\n")))?;
     }
     Ok(())
 }
+
+const DEFAULT_PATHS: [&str; 17] = [
+    "../a1-gtp-proxy/src/GTP-C-INIT_V5.tcl",
+    "../a1-gtp-proxy/src/GTP-C-clientAcceptV5.tcl",
+    "../a1-gtp-proxy/src/GTP-C-clientAcceptv3.tcl",
+    "../a1-gtp-proxy/src/GTP-C-clientAcceptv31.tcl",
+    "../a1-gtp-proxy/src/GTP-C-clientEgressV5.tcl",
+    "../a1-gtp-proxy/src/GTP-C-clientEgressv3.tcl",
+    "../a1-gtp-proxy/src/GTP-C-clientIngressV5.tcl",
+    "../a1-gtp-proxy/src/GTP-C-clientIngressv3.tcl",
+    "../a1-gtp-proxy/src/GTP-C-clientIngressv31.tcl",
+    "../a1-gtp-proxy/src/GTP-C-variables_V5.tcl",
+    "../a1-gtp-proxy/src/GTP-U_v4.tcl",
+    "../a1-gtp-proxy/src/GTP-Uv3.tcl",
+    "../a1-gtp-proxy/src/GTP-Uv31.tcl",
+    "../a1-gtp-proxy/src/lib_GTPutil_V4.tcl",
+    "../a1-gtp-proxy/src/lib_GTPutil_V5.tcl",
+    "../a1-gtp-proxy/src/lib_GTPutilv3.tcl",
+    "../a1-gtp-proxy/src/lib_LogUtil.tcl",
+];
@@ -0,0 +1,413 @@
+// Opt-in rewrites of the tree that keep its behaviour, run between parsing and formatting.
+// Anything that can't be rewritten safely is reported as a warning instead.
+
+use crate::{
+    ast::{
+        Address, Ast, Endpoint, GtpArgument, GtpCommand, SnatTarget, Statement, SwitchArm,
+        SwitchOption, TryHandler, UdpCommand, Word, WordPart,
+    },
+    config::Style,
+};
+
+pub enum Warning {
+    CommentInSwitch(Vec<u8>),  // Tcl reads its words as patterns and bodies
+    ArmsInSwitch(Vec<u8>),     // the same, with whole pairs, so the arms after it stay in place
+    DefaultNotLast,            // matches the literal text "default" instead
+    DuplicatePattern(Vec<u8>), // the later arm is never taken
+    Unreachable(Vec<u8>),      // arm after a pattern that matches everything
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CommentInSwitch(comment) => write!(
+                f,
+                "switch: \"#{}\" is not a comment, Tcl reads its words as patterns and bodies \
+                 and shifts every arm after it, set switch_comments = move to move it into the \
+                 body of the next arm",
+                String::from_utf8_lossy(comment)
+            ),
+            Self::ArmsInSwitch(comment) => write!(
+                f,
+                "switch: \"#{}\" is not a comment, Tcl reads its words as patterns and bodies",
+                String::from_utf8_lossy(comment)
+            ),
+            Self::DefaultNotLast => write!(f, "switch: default is not the last pattern"),
+            Self::DuplicatePattern(pattern) => write!(
+                f,
+                "switch: duplicate pattern {}",
+                String::from_utf8_lossy(pattern)
+            ),
+            Self::Unreachable(pattern) => write!(
+                f,
+                "switch: pattern {} is unreachable",
+                String::from_utf8_lossy(pattern)
+            ),
+        }
+    }
+}
+
+pub struct Normalizer {
+    style: Style,
+    warnings: Vec<Warning>,
+}
+
+impl Normalizer {
+    pub fn with_style(style: Style) -> Self {
+        Self {
+            style,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn normalize(mut self, ast: &mut Ast) -> Vec<Warning> {
+        self.run(ast);
+        self.warnings
+    }
+
+    fn run(&mut self, ast: &mut Ast) {
+        match ast {
+            Ast::Block(trees) => trees.iter_mut().for_each(|tree| self.run(tree)),
+            Ast::Procedure { body, .. } | Ast::Namespace { body, .. } | Ast::When { body, .. } => {
+                self.run(body)
+            }
+            Ast::If {
+                condition_body_clauses,
+                maybe_block_if_false,
+            } => {
+                for (condition, body) in condition_body_clauses {
+                    self.run_word(condition);
+                    self.run(body);
+                }
+                if let Some(body) = maybe_block_if_false {
+                    self.run(body);
+                }
+            }
+            Ast::Switch {
+                options,
+                subject,
+                arms,
+            } => {
+                self.run_word(subject);
+                for arm in arms.iter_mut() {
+                    if let SwitchArm::Pattern {
+                        maybe_body: Some(body),
+                        ..
+                    } = arm
+                    {
+                        self.run(body);
+                    }
+                }
+                if self.style.move_switch_comments {
+                    move_comments(arms);
+                }
+                for arm in arms.iter() {
+                    if let SwitchArm::Comment(comment) = arm {
+                        self.warnings.push(match is_arms(comment) {
+                            true => Warning::ArmsInSwitch(comment.clone()),
+                            false => Warning::CommentInSwitch(comment.clone()),
+                        });
+                    }
+                }
+                if self.style.normalize_switches {
+                    sort_fallthrough_groups(arms);
+                    self.check_arms(options, arms);
+                }
+            }
+            Ast::Catch { body, .. } => self.run(body),
+            Ast::Try {
+                body,
+                handlers,
+                maybe_finally,
+            } => {
+                self.run(body);
+                for handler in handlers {
+                    let (TryHandler::On { maybe_body, .. } | TryHandler::Trap { maybe_body, .. }) =
+                        handler;
+                    if let Some(body) = maybe_body {
+                        self.run(body);
+                    }
+                }
+                if let Some(body) = maybe_finally {
+                    self.run(body);
+                }
+            }
+            Ast::Command { words, .. } => words.iter_mut().for_each(|word| self.run_word(word)),
+            Ast::Gtp(command) => match command.as_mut() {
+                GtpCommand::Header { arguments } | GtpCommand::Ie { arguments, .. } => {
+                    for argument in arguments {
+                        match argument {
+                            GtpArgument::Option((_, word)) | GtpArgument::Word(word) => {
+                                self.run_word(word)
+                            }
+                        }
+                    }
+                }
+                GtpCommand::Parse {
+                    payload,
+                    maybe_length,
+                } => {
+                    self.run_word(payload);
+                    maybe_length.iter_mut().for_each(|word| self.run_word(word));
+                }
+                GtpCommand::New {
+                    version,
+                    message_type,
+                } => {
+                    self.run_word(version);
+                    self.run_word(message_type);
+                }
+                GtpCommand::Respond { message } | GtpCommand::Forward { message } => {
+                    self.run_word(message)
+                }
+            },
+            Ast::Udp(command) => match command.as_mut() {
+                UdpCommand::Payload { arguments } => {
+                    arguments.iter_mut().for_each(|word| self.run_word(word))
+                }
+                UdpCommand::PayloadReplace {
+                    offset,
+                    length,
+                    data,
+                } => {
+                    self.run_word(offset);
+                    self.run_word(length);
+                    self.run_word(data);
+                }
+                UdpCommand::Respond { data } => self.run_word(data),
+                UdpCommand::Drop => (),
+            },
+            Ast::Statement(Statement::Set {
+                maybe_value: Some(value),
+                ..
+            }) => self.run_word(value),
+            Ast::Statement(Statement::Log { message, .. }) => self.run_word(message),
+            Ast::Statement(Statement::Return {
+                options,
+                maybe_value,
+            }) => {
+                options.iter_mut().for_each(|(_, word)| self.run_word(word));
+                if let Some(value) = maybe_value {
+                    self.run_word(value);
+                }
+            }
+            Ast::Statement(Statement::Snat {
+                target: SnatTarget::Endpoint(endpoint),
+            })
+            | Ast::Statement(Statement::Node { endpoint }) => self.run_endpoint(endpoint),
+            Ast::Statement(
+                Statement::Pool { name, maybe_member } | Statement::SnatPool { name, maybe_member },
+            ) => {
+                self.run_word(name);
+                if let Some(member) = maybe_member {
+                    self.run_endpoint(member);
+                }
+            }
+            Ast::Comment(_)
+            | Ast::TrailingComment(_)
+            | Ast::Statement(_)
+            | Ast::Verbatim(_)
+            | Ast::EmptyLine => (),
+        }
+    }
+
+    fn run_endpoint(&mut self, endpoint: &mut Endpoint) {
+        if let Address::Word(word) = &mut endpoint.address {
+            self.run_word(word);
+        }
+        if let Some(port) = &mut endpoint.maybe_port {
+            self.run_word(port);
+        }
+    }
+
+    fn run_word(&mut self, word: &mut Word) {
+        match word {
+            Word::Bare(parts) | Word::Quoted(parts) => {
+                for part in parts {
+                    if let WordPart::CommandSubstitution(body) = part {
+                        self.run(body);
+                    }
+                }
+            }
+            Word::Script(body) | Word::CommandSubstitution(body) => self.run(body),
+            Word::Expansion(word) => self.run_word(word),
+            Word::Braced(_) | Word::Expression(_) | Word::List(_) | Word::Variable(_) => (),
+        }
+    }
+
+    // arms are matched in order, the first one that matches wins
+    fn check_arms(&mut self, options: &[SwitchOption], arms: &[SwitchArm]) {
+        // the last mode option wins
+        let is_glob = options.iter().rfind(|o| {
+            matches!(
+                o,
+                SwitchOption::Exact | SwitchOption::Glob | SwitchOption::Regexp
+            )
+        }) == Some(&SwitchOption::Glob);
+        let is_nocase = options.contains(&SwitchOption::NoCase);
+        // the patterns of arms left as read aren't known
+        if arms.iter().any(|arm| matches!(arm, SwitchArm::Verbatim(_))) {
+            return;
+        }
+        let mut seen: Vec<Vec<u8>> = Vec::new();
+        let mut is_exhausted = false;
+        let patterns: Vec<&Word> = arms
+            .iter()
+            .filter_map(|arm| match arm {
+                SwitchArm::Pattern { pattern, .. } => Some(pattern),
+                SwitchArm::Comment(_) | SwitchArm::Verbatim(_) => None,
+            })
+            .collect();
+        for (idx, pattern) in patterns.iter().enumerate() {
+            let Some(text) = pattern.literal_text() else {
+                continue;
+            };
+            if is_exhausted {
+                self.warnings.push(Warning::Unreachable(text));
+                continue;
+            }
+            if pattern.is_default() {
+                if idx + 1 != patterns.len() {
+                    self.warnings.push(Warning::DefaultNotLast);
+                }
+                continue;
+            }
+            let key = match is_nocase {
+                true => text.to_ascii_lowercase(),
+                false => text.clone(),
+            };
+            if seen.contains(&key) {
+                self.warnings.push(Warning::DuplicatePattern(text));
+                continue;
+            }
+            is_exhausted = is_glob && key.iter().all(|&c| c == b'*');
+            seen.push(key);
+        }
+    }
+}
+
+// the comments go to the start of the body of the next arm that has one
+// a comment that Tcl reads as whole patterns and bodies is an arm that works, and is kept
+fn move_comments(arms: &mut Vec<SwitchArm>) {
+    let mut comments: Vec<Vec<u8>> = Vec::new();
+    for arm in std::mem::take(arms) {
+        match arm {
+            SwitchArm::Comment(comment) if !is_arms(&comment) => comments.push(comment),
+            SwitchArm::Pattern {
+                pattern,
+                maybe_body: Some(body),
+            } if !comments.is_empty() => {
+                let mut trees: Vec<Ast> = comments
+                    .drain(..)
+                    .map(|comment| Ast::Comment(comment.trim_ascii_start().to_vec()))
+                    .collect();
+                match body {
+                    Ast::Block(mut body) => trees.append(&mut body),
+                    body => trees.push(body),
+                }
+                arms.push(SwitchArm::Pattern {
+                    pattern,
+                    maybe_body: Some(Ast::Block(trees)),
+                });
+            }
+            arm => arms.push(arm),
+        }
+    }
+    // nothing to move them into after the last arm
+    arms.extend(comments.into_iter().map(SwitchArm::Comment));
+}
+
+// whether the words of `#<comment>` are complete pairs of a pattern and a body
+fn is_arms(comment: &[u8]) -> bool {
+    let text = [b"#".as_slice(), comment].concat();
+    let mut rest = text.as_slice();
+    let mut count = 0;
+    loop {
+        rest = rest.trim_ascii_start();
+        let Some(&first) = rest.first() else {
+            return count % 2 == 0;
+        };
+        let maybe_len = match first {
+            b'{' | b'"' => closing_len(rest),
+            _ => Some(rest.iter().take_while(|c| !c.is_ascii_whitespace()).count()),
+        };
+        // a braced or quoted word must be followed by a space
+        match maybe_len {
+            Some(len) if rest.get(len).is_none_or(|c| c.is_ascii_whitespace()) => {
+                rest = &rest[len..];
+                count += 1;
+            }
+            _ => return false,
+        }
+    }
+}
+
+// length of the braced or quoted word at the start of the text, brackets included
+fn closing_len(text: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut is_escaped = false;
+    for (idx, &c) in text.iter().enumerate() {
+        match c {
+            _ if is_escaped => is_escaped = false,
+            b'\\' => is_escaped = true,
+            b'"' if text[0] == b'"' && idx > 0 => return Some(idx + 1),
+            b'{' if text[0] == b'{' => depth += 1,
+            b'}' if text[0] == b'{' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// the labels of a group of fallthrough arms share the body of its last arm, so their order
+// doesn't matter, unless one of them is the default which only counts as the last pattern
+fn sort_fallthrough_groups(arms: &mut [SwitchArm]) {
+    for group in arms.split_mut(|arm| matches!(arm, SwitchArm::Comment(_) | SwitchArm::Verbatim(_)))
+    {
+        let mut start = 0;
+        while start < group.len() {
+            let len = match group[start..].iter().position(has_body) {
+                Some(idx) => idx + 1,
+                None => group.len() - start,
+            };
+            sort_patterns(&mut group[start..start + len]);
+            start += len;
+        }
+    }
+}
+
+fn sort_patterns(group: &mut [SwitchArm]) {
+    let mut patterns = Vec::new();
+    for arm in group.iter_mut() {
+        let SwitchArm::Pattern { pattern, .. } = arm else {
+            return;
+        };
+        if pattern.literal_text().is_none() || pattern.is_default() {
+            return;
+        }
+        patterns.push(pattern);
+    }
+    let mut sorted: Vec<Word> = patterns
+        .iter_mut()
+        .map(|pattern| std::mem::replace(*pattern, Word::Bare(Vec::new())))
+        .collect();
+    sorted.sort_by_key(Word::literal_text);
+    for (pattern, sorted) in patterns.into_iter().zip(sorted) {
+        *pattern = sorted;
+    }
+}
+
+fn has_body(arm: &SwitchArm) -> bool {
+    matches!(
+        arm,
+        SwitchArm::Pattern {
+            maybe_body: Some(_),
+            ..
+        }
+    )
+}
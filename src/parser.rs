@@ -1,333 +1,680 @@
 use crate::{
-    ast::{Ast, Statement},
+    ast::{
+        Address, Ast, Endpoint, GtpArgument, GtpCommand, GtpIeAction, SnatTarget, Statement,
+        SwitchArm, SwitchOption, TryHandler, UdpCommand, Variable, Word, WordPart,
+    },
     lexer::Token,
+    signature::{ArgumentKind, Signatures},
 };
 
-pub struct Parser {}
+pub struct Parser {
+    signatures: Signatures,
+    namespace: Vec<u8>, // of the `namespace eval` being parsed, empty at the global level
+}
 
 #[derive(Debug)]
 pub enum ParserFail {
+    WhenBlock,
     ElseIfBlock,
     SwitchBlock,
-    Expression,
+    TryBlock,
     BracketMismatch,
+    QuoteMismatch,
     NoNewline,  // expected newline
     UnknownAST, // no tokens matched an AST block
-    Other,      // TODO: remove this
 }
 type Result<T> = std::result::Result<T, ParserFail>;
 
+// the tokens of a single word of a command
+type RawWord<'a> = &'a [Token];
+
 impl Parser {
-    pub fn new() -> Self {
-        Self {}
+    pub fn with_signatures(signatures: Signatures) -> Self {
+        Self {
+            signatures,
+            namespace: Vec::new(),
+        }
     }
 
     pub fn parse(self, tokens: &[Token]) -> Result<Ast> {
-        Ok(Parser::try_parse(tokens)?.0)
+        Ok(self.try_parse(tokens)?.0)
     }
 
-    fn try_parse_if(mut tokens: &[Token]) -> Result<(Ast, usize)> {
+    fn try_parse_if(&self, words: &[RawWord]) -> Result<Ast> {
         println!("parsing if");
         let mut condition_body_clauses = Vec::new();
-        let mut consumed = 1; // starts from 1 for the if keyword
-        tokens = &tokens[consumed..];
-
-        let condition_tokens = Parser::try_extract_block(tokens)?;
-        tokens = &tokens[condition_tokens.len() + 2..];
-        consumed += condition_tokens.len() + 2;
-
-        let body_tokens = Parser::try_extract_block(tokens)?;
-        tokens = &tokens[body_tokens.len() + 2..];
-        consumed += body_tokens.len() + 2;
-
-        let if_condition = Parser::parse_vec(condition_tokens);
-        let (body_if_true, _) = Parser::try_parse(body_tokens)?;
-        condition_body_clauses.push((if_condition, body_if_true));
-
-        // eat a newline if possible
-        if let Some(Token::Newline) = tokens.get(0) {
-            tokens = &tokens[1..];
-        }
-
-        let maybe_block_if_false = match (tokens.get(0), tokens.get(1)) {
-            (Some(Token::KeywordElseIf), Some(Token::LCurlyBracket)) => {
-                let (ast, consumed_rem) = Parser::try_parse_if(tokens)?;
-                consumed += consumed_rem + 1; // +1 for the elseif keyword
-                match ast {
-                    Ast::If {
-                        condition_body_clauses: elseif_clauses,
-                        maybe_block_if_false,
-                    } => {
-                        condition_body_clauses.extend(elseif_clauses);
-                        maybe_block_if_false
-                    }
-                    _ => return Err(ParserFail::ElseIfBlock),
+        let mut maybe_block_if_false = None;
+
+        let mut rem_words = &words[1..]; // skip the if keyword
+        loop {
+            let [condition, body, rem @ ..] = rem_words else {
+                return Err(ParserFail::ElseIfBlock);
+            };
+            // `then` is optional
+            let (body, rem) = match (Parser::word_is(body, b"then"), rem) {
+                (true, [body, rem @ ..]) => (body, rem),
+                (true, []) => return Err(ParserFail::ElseIfBlock),
+                (false, _) => (body, rem),
+            };
+
+            let body_tokens = Parser::try_extract_braced(body).ok_or(ParserFail::ElseIfBlock)?;
+            condition_body_clauses.push((
+                self.try_parse_word(condition, ArgumentKind::Expression)?,
+                self.try_parse_body(body_tokens)?,
+            ));
+
+            match rem {
+                [] => break,
+                [keyword, rem @ ..] if Parser::word_is(keyword, b"elseif") => rem_words = rem,
+                [keyword, body] if Parser::word_is(keyword, b"else") => {
+                    let else_body_tokens =
+                        Parser::try_extract_braced(body).ok_or(ParserFail::ElseIfBlock)?;
+                    let block_if_false = self.try_parse_body(else_body_tokens)?;
+                    maybe_block_if_false = Some(Box::new(block_if_false));
+                    break;
                 }
+                _ => return Err(ParserFail::ElseIfBlock),
             }
-            (Some(Token::KeywordElse), Some(Token::LCurlyBracket)) => {
-                let else_body_tokens = Parser::try_extract_block(&tokens[1..])?;
-                consumed += else_body_tokens.len() + 1 + 2; // +1 for the else keyword, +2 for brackets
-                let (block_if_false, _) = Parser::try_parse(else_body_tokens)?;
-                Some(Box::new(block_if_false))
-            }
-            _ => None,
-        };
+        }
 
-        return Ok((
-            Ast::If {
-                condition_body_clauses,
-                maybe_block_if_false,
-            },
-            consumed,
-        ));
+        Ok(Ast::If {
+            condition_body_clauses,
+            maybe_block_if_false,
+        })
     }
 
-    fn try_parse_when(mut tokens: &[Token]) -> Result<(Ast, usize)> {
+    fn try_parse_when(&self, words: &[RawWord]) -> Result<Ast> {
         println!("parsing when");
-        let Token::Identifier(event_name) = &tokens[1] else {
-            unreachable!();
+        let [_, event_name, options @ .., body] = words else {
+            return Err(ParserFail::UnknownAST);
+        };
+        let Some(body_tokens) = Parser::try_extract_braced(body) else {
+            return Err(ParserFail::UnknownAST);
         };
 
-        let mut consumed = 2; // starts from 2 for the when keyword & the event name
-        tokens = &tokens[consumed..];
-
-        let body_tokens = Parser::try_extract_block(tokens)?;
-        consumed += body_tokens.len() + 2;
+        // `priority N` and `timing on|off`, in any order
+        let mut maybe_priority = None;
+        let mut maybe_timing = None;
+        for option in options.chunks(2) {
+            let [name, value] = option else {
+                return Err(ParserFail::WhenBlock);
+            };
+            let value = Parser::parse_vec(value);
+            match Parser::parse_vec(name).as_slice() {
+                b"priority" if maybe_priority.is_none() => {
+                    let priority = std::str::from_utf8(&value)
+                        .ok()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|&p| p <= 1000)
+                        .ok_or(ParserFail::WhenBlock)?;
+                    maybe_priority = Some(priority);
+                }
+                b"timing" if maybe_timing.is_none() => {
+                    maybe_timing = match value.as_slice() {
+                        b"on" | b"enable" => Some(true),
+                        b"off" | b"disable" => Some(false),
+                        _ => return Err(ParserFail::WhenBlock),
+                    };
+                }
+                _ => return Err(ParserFail::WhenBlock),
+            }
+        }
 
-        let (body_if_true, _) = Parser::try_parse(body_tokens)?;
+        let body_if_true = self.try_parse_body(body_tokens)?;
 
-        return Ok((
-            Ast::When {
-                event_name: event_name.to_vec(),
-                body: Box::new(body_if_true),
-            },
-            consumed,
-        ));
+        Ok(Ast::When {
+            event_name: Parser::parse_vec(event_name),
+            maybe_priority,
+            maybe_timing,
+            body: Box::new(body_if_true),
+        })
     }
 
-    fn try_parse_set(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing set");
-        let Token::Identifier(data) = &tokens[1] else {
-            unreachable!();
+    fn try_parse_proc(&self, words: &[RawWord]) -> Result<Ast> {
+        let [_, name, parameters, body] = words else {
+            return Err(ParserFail::UnknownAST);
         };
-        let mut consumed = 2; // starts from 2 for the set keyword & the identifier
+        let Some(body_tokens) = Parser::try_extract_braced(body) else {
+            return Err(ParserFail::UnknownAST);
+        };
+        let name = Parser::parse_vec(name);
+        let qualified_name = self.qualify(&name);
 
-        // TODO: fix space iterator logic, as no spaces should be in data
-        let mut space_iterator = data.split(|&x| x == b' ');
-        let identifier = space_iterator.next().ok_or(ParserFail::Other)?.to_vec();
-        let mut value = space_iterator
-            .next()
-            .map(|x| x.to_vec())
-            .unwrap_or(Vec::new());
+        Ok(Ast::Procedure {
+            name,
+            qualified_name,
+            parameters: Parser::try_extract_list_elements(parameters)?,
+            body: Box::new(self.try_parse_body(body_tokens)?),
+        })
+    }
 
-        let rem_tokens = Parser::try_extract_until_newline(&tokens[2..])?;
-        consumed += rem_tokens.len() + 1;
-        value.extend(Parser::parse_vec(rem_tokens));
+    // `namespace eval <name> <script>`, other namespace commands are generic commands
+    fn try_parse_namespace(&self, words: &[RawWord]) -> Result<Ast> {
+        let [_, eval, name, body] = words else {
+            return self.try_parse_command(words);
+        };
+        let (true, Some(body_tokens)) = (
+            Parser::word_is(eval, b"eval"),
+            Parser::try_extract_braced(body),
+        ) else {
+            return self.try_parse_command(words);
+        };
+        let name = Parser::parse_vec(name);
+        let parser = Parser {
+            signatures: self.signatures.clone(),
+            namespace: self.qualify(&name),
+        };
 
-        return Ok((
-            Ast::Statement(Statement::Set { identifier, value }),
-            consumed,
-        ));
+        Ok(Ast::Namespace {
+            name,
+            body: Box::new(parser.try_parse_body(body_tokens)?),
+        })
     }
 
-    fn try_parse_log(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing log");
-        let Some(Token::Identifier(data)) = tokens.get(1) else {
-            unreachable!();
+    // the fully qualified form of a name relative to the current namespace
+    fn qualify(&self, name: &[u8]) -> Vec<u8> {
+        match name.starts_with(b"::") {
+            true => name.to_vec(),
+            false => [&self.namespace, b"::".as_slice(), name].concat(),
+        }
+    }
+
+    // a body that isn't braced, such as `catch $script result`, is a generic command
+    fn try_parse_catch(&self, words: &[RawWord]) -> Result<Ast> {
+        let [_, body, variables @ ..] = words else {
+            return self.try_parse_command(words);
+        };
+        let Some(body_tokens) = Parser::try_extract_braced(body) else {
+            return self.try_parse_command(words);
         };
-        let consumed = 3; // starts from 3 for the log keyword, the bucket, the value
-        let Some(Token::Other(content)) = tokens.get(2) else {
-            unreachable!();
+        let body = self.try_parse_body(body_tokens)?;
+
+        let (maybe_result_variable, maybe_options_variable) = match variables {
+            [] => (None, None),
+            [result] => (Some(Parser::parse_vec(result)), None),
+            [result, options] => (
+                Some(Parser::parse_vec(result)),
+                Some(Parser::parse_vec(options)),
+            ),
+            _ => return self.try_parse_command(words),
         };
-        let bucket = data.to_vec();
-        let value = content.to_vec();
 
-        return Ok((Ast::Statement(Statement::Log { bucket, value }), consumed));
+        Ok(Ast::Catch {
+            body: Box::new(body),
+            maybe_result_variable,
+            maybe_options_variable,
+        })
     }
 
-    fn try_parse_statement(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing other statement");
-        let mut consumed = 0;
+    // so is a try with a body or a handler that isn't braced, such as `try $body on error ...`
+    fn try_parse_try(&self, words: &[RawWord]) -> Result<Ast> {
+        let [_, body, rem_words @ ..] = words else {
+            return self.try_parse_command(words);
+        };
+        let Some(body_tokens) = Parser::try_extract_braced(body) else {
+            return self.try_parse_command(words);
+        };
+        let body = self.try_parse_body(body_tokens)?;
 
-        let statement_tokens = Parser::try_extract_until_newline(tokens)?;
-        consumed += statement_tokens.len() + 1;
-        let data = Parser::parse_vec(statement_tokens);
+        let mut handlers = Vec::new();
+        let mut maybe_finally = None;
 
-        return Ok((Ast::Statement(Statement::Other { data }), consumed));
+        let mut rem_words = rem_words;
+        loop {
+            match rem_words {
+                [] => break,
+                [keyword, pattern, variables, body, rem @ ..]
+                    if (Parser::word_is(keyword, b"on") || Parser::word_is(keyword, b"trap"))
+                        && (Parser::try_extract_braced(body).is_some()
+                            || Parser::word_is(body, b"-")) =>
+                {
+                    handlers.push(self.try_parse_try_handler(keyword, pattern, variables, body)?);
+                    rem_words = rem;
+                }
+                [keyword, body] if Parser::word_is(keyword, b"finally") => {
+                    let Some(finally_tokens) = Parser::try_extract_braced(body) else {
+                        return self.try_parse_command(words);
+                    };
+                    let finally_body = self.try_parse_body(finally_tokens)?;
+                    maybe_finally = Some(Box::new(finally_body));
+                    break;
+                }
+                _ => return self.try_parse_command(words),
+            }
+        }
+
+        Ok(Ast::Try {
+            body: Box::new(body),
+            handlers,
+            maybe_finally,
+        })
     }
 
-    fn try_parse_node(tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing node statement");
-        let mut consumed = 1;
+    fn try_parse_try_handler(
+        &self,
+        keyword: RawWord,
+        pattern: RawWord,
+        variables: RawWord,
+        body: RawWord,
+    ) -> Result<TryHandler> {
+        let pattern = Parser::try_extract_list_elements(pattern)?;
+        let variables = Parser::try_extract_list_elements(variables)?;
+
+        let maybe_body = match Parser::try_extract_braced(body) {
+            Some(body_tokens) => Some(self.try_parse_body(body_tokens)?),
+            None if Parser::word_is(body, b"-") => None, // fallthrough
+            None => return Err(ParserFail::TryBlock),
+        };
+
+        if Parser::word_is(keyword, b"trap") {
+            return Ok(TryHandler::Trap {
+                pattern,
+                variables,
+                maybe_body,
+            });
+        }
+        // the code of `on` is a single word
+        let [code] = <[Vec<u8>; 1]>::try_from(pattern).map_err(|_| ParserFail::TryBlock)?;
+        Ok(TryHandler::On {
+            code,
+            variables,
+            maybe_body,
+        })
+    }
 
-        let mut rem_tokens = Parser::try_extract_until_newline(&tokens[1..])?;
-        consumed += rem_tokens.len() + 1;
+    // a target that isn't a plain variable name, such as `set $name 1`, is a generic command
+    fn try_parse_set(&self, words: &[RawWord]) -> Result<Ast> {
+        println!("parsing set");
+        let (target, maybe_value) = match words {
+            [_, target] => (target, None),
+            [_, target, value] => (target, Some(value)),
+            _ => return self.try_parse_command(words),
+        };
+        let (target, rem, consumed) = self.try_parse_variable(target)?;
+        if consumed != words[1].len() || !rem.is_empty() || target.name.is_empty() {
+            return self.try_parse_command(words);
+        }
+        let maybe_value = match maybe_value {
+            Some(value) => Some(Box::new(self.try_parse_word(value, ArgumentKind::Word)?)),
+            None => None,
+        };
 
-        let (ip_address, n) = Parser::try_parse_expression(&rem_tokens)?;
-        rem_tokens = &rem_tokens[n..];
+        Ok(Ast::Statement(Statement::Set {
+            target,
+            maybe_value,
+        }))
+    }
 
-        let (port, n) = Parser::try_parse_expression(&rem_tokens)?;
-        rem_tokens = &rem_tokens[n..];
+    fn try_parse_log(&self, words: &[RawWord]) -> Result<Ast> {
+        println!("parsing log");
+        let mut flags = Vec::new();
+        let mut rem_words = &words[1..]; // skip the log keyword
+        while let [flag, rem @ ..] = rem_words {
+            let flag = Parser::parse_vec(flag);
+            if !flag.starts_with(b"-") || rem.is_empty() {
+                break;
+            }
+            flags.push(flag);
+            rem_words = rem;
+        }
+        let (maybe_destination, maybe_facility, message) = match rem_words {
+            [message] => (None, None, message),
+            // `local0.` or `10.0.0.1:514`, a facility never starts with a digit nor has a port
+            [word, message] => match Parser::parse_vec(word) {
+                destination
+                    if destination.first().is_some_and(u8::is_ascii_digit)
+                        || destination.contains(&b':') =>
+                {
+                    (Some(destination), None, message)
+                }
+                facility => (None, Some(facility), message),
+            },
+            [destination, facility, message] => (
+                Some(Parser::parse_vec(destination)),
+                Some(Parser::parse_vec(facility)),
+                message,
+            ),
+            _ => return Err(ParserFail::UnknownAST),
+        };
+        let message = Box::new(self.try_parse_word(message, ArgumentKind::Word)?);
 
-        assert!(rem_tokens.is_empty());
+        Ok(Ast::Statement(Statement::Log {
+            flags,
+            maybe_destination,
+            maybe_facility,
+            message,
+        }))
+    }
 
-        return Ok((
-            Ast::Statement(Statement::Node { ip_address, port }),
-            consumed,
-        ));
+    fn try_parse_node(&self, words: &[RawWord]) -> Result<Ast> {
+        println!("parsing node statement");
+        let endpoint = Box::new(self.try_parse_endpoint(&words[1..])?);
+        Ok(Ast::Statement(Statement::Node { endpoint }))
     }
 
-    fn try_parse_pool(tokens: &[Token]) -> Result<(Ast, usize)> {
+    // `pool <name>` or `pool <name> member <address> [<port>]`, the same for snatpool
+    fn try_parse_pool(&self, words: &[RawWord]) -> Result<Ast> {
         println!("parsing pool statement");
-        let mut consumed = 1; // pool
-
-        let rem_tokens = Parser::try_extract_until_newline(&tokens[1..])?;
-        consumed += rem_tokens.len() + 1;
+        let [keyword, name, member @ ..] = words else {
+            return Err(ParserFail::UnknownAST);
+        };
+        let name = Box::new(self.try_parse_word(name, ArgumentKind::Word)?);
+        let maybe_member = match member {
+            [] => None,
+            [member, endpoint @ ..] if Parser::word_is(member, b"member") => {
+                Some(Box::new(self.try_parse_endpoint(endpoint)?))
+            }
+            _ => return Err(ParserFail::UnknownAST),
+        };
 
-        return Ok((
-            Ast::Statement(Statement::Pool {
-                identifier: Parser::parse_vec(rem_tokens),
-            }),
-            consumed,
-        ));
+        Ok(Ast::Statement(
+            match Parser::parse_vec(keyword).as_slice() {
+                b"snatpool" => Statement::SnatPool { name, maybe_member },
+                _ => Statement::Pool { name, maybe_member },
+            },
+        ))
     }
 
-    fn try_parse_snat(tokens: &[Token]) -> Result<(Ast, usize)> {
+    fn try_parse_snat(&self, words: &[RawWord]) -> Result<Ast> {
         println!("parsing snat statement");
-        let mut consumed = 1;
+        let target = match &words[1..] {
+            [word] if Parser::word_is(word, b"automap") => SnatTarget::Automap,
+            [word] if Parser::word_is(word, b"none") => SnatTarget::None,
+            endpoint => SnatTarget::Endpoint(Box::new(self.try_parse_endpoint(endpoint)?)),
+        };
+        Ok(Ast::Statement(Statement::Snat { target }))
+    }
+
+    // `<address> [<port>]`
+    fn try_parse_endpoint(&self, words: &[RawWord]) -> Result<Endpoint> {
+        let (address, maybe_port) = match words {
+            [address] => (address, None),
+            [address, port] => (address, Some(port)),
+            _ => return Err(ParserFail::UnknownAST),
+        };
+        let address = match Parser::try_parse_address(&Parser::parse_vec(address)) {
+            Some(address) => address,
+            None => Address::Word(Box::new(self.try_parse_word(address, ArgumentKind::Word)?)),
+        };
+        let maybe_port = match maybe_port {
+            Some(port) => Some(Box::new(self.try_parse_word(port, ArgumentKind::Word)?)),
+            None => None,
+        };
+        Ok(Endpoint {
+            address,
+            maybe_port,
+        })
+    }
+
+    // a literal IPv4 or IPv6 address, such as `10.0.0.1%2:80`
+    fn try_parse_address(text: &[u8]) -> Option<Address> {
+        if text.is_empty()
+            || !text
+                .iter()
+                .all(|c| c.is_ascii_hexdigit() || b".:%".contains(c))
+        {
+            return None;
+        }
+        let is_ipv6 = text.iter().filter(|&&c| c == b':').count() > 1;
+        // the port follows a colon in IPv4, and the dot after the last colon in IPv6
+        let port_idx = match is_ipv6 {
+            true => {
+                let tail = text.iter().rposition(|&c| c == b':')?;
+                let dots = text[tail..].iter().filter(|&&c| c == b'.').count();
+                match dots {
+                    1 | 4 => text.iter().rposition(|&c| c == b'.'), // after a port or embedded IPv4
+                    _ => None,
+                }
+            }
+            false => text.iter().position(|&c| c == b':'),
+        };
+        let (rest, maybe_port) = match port_idx {
+            Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+            None => (text, None),
+        };
+        let (ip, maybe_route_domain) = match rest.iter().position(|&c| c == b'%') {
+            Some(idx) => (&rest[..idx], Some(&rest[idx + 1..])),
+            None => (rest, None),
+        };
+        let is_number = |x: &[u8]| !x.is_empty() && x.iter().all(u8::is_ascii_digit);
+        let is_valid = match is_ipv6 {
+            true => ip.contains(&b':'),
+            false => {
+                ip.split(|&c| c == b'.').count() == 4 && ip.split(|&c| c == b'.').all(is_number)
+            }
+        };
+        (is_valid && maybe_route_domain.is_none_or(is_number) && maybe_port.is_none_or(is_number))
+            .then(|| Address::Literal {
+                ip: ip.to_vec(),
+                maybe_route_domain: maybe_route_domain.map(|x| x.to_vec()),
+                maybe_port: maybe_port.map(|x| x.to_vec()),
+            })
+    }
 
-        let mut rem_tokens = Parser::try_extract_until_newline(&tokens[1..])?;
-        consumed += rem_tokens.len() + 1;
+    // options come in pairs, an odd word at the end is the value
+    fn try_parse_return(&self, words: &[RawWord]) -> Result<Ast> {
+        let arguments = &words[1..];
+        let (pairs, maybe_value) = match arguments.len() % 2 {
+            0 => (arguments, None),
+            _ => (&arguments[..arguments.len() - 1], arguments.last()),
+        };
+        let options = pairs
+            .chunks(2)
+            .map(|pair| match Parser::parse_vec(pair[0]) {
+                name if name.starts_with(b"-") => {
+                    Ok((name, self.try_parse_word(pair[1], ArgumentKind::Word)?))
+                }
+                _ => Err(ParserFail::UnknownAST),
+            })
+            .collect::<Result<_>>()?;
+        let maybe_value = match maybe_value {
+            Some(value) => Some(Box::new(self.try_parse_word(value, ArgumentKind::Word)?)),
+            None => None,
+        };
+        Ok(Ast::Statement(Statement::Return {
+            options,
+            maybe_value,
+        }))
+    }
 
-        let (ip_address, n) = Parser::try_parse_expression(&rem_tokens)?;
-        rem_tokens = &rem_tokens[n..];
+    fn try_parse_command(&self, words: &[RawWord]) -> Result<Ast> {
+        let name = Parser::parse_vec(words[0]);
+        let arguments = &words[1..];
 
-        let (port, n) = Parser::try_parse_expression(&rem_tokens)?;
-        rem_tokens = &rem_tokens[n..];
+        let texts: Vec<Vec<u8>> = arguments.iter().map(|w| Parser::parse_vec(w)).collect();
+        // a form of the command without a signature has plain words only
+        let kinds = self
+            .signatures
+            .resolve(&name, &texts)
+            .unwrap_or_else(|| vec![ArgumentKind::Word; arguments.len()]);
 
-        assert!(rem_tokens.is_empty());
+        let (maybe_subcommand, arguments, kinds) = match kinds.split_first() {
+            Some((ArgumentKind::Subcommand, kinds)) => {
+                (Some(texts[0].clone()), &arguments[1..], kinds)
+            }
+            _ => (None, arguments, kinds.as_slice()),
+        };
+        let words = arguments
+            .iter()
+            .zip(kinds)
+            .map(|(word, &kind)| self.try_parse_word(word, kind))
+            .collect::<Result<Vec<_>>>()?;
 
-        return Ok((
-            Ast::Statement(Statement::Snat { ip_address, port }),
-            consumed,
-        ));
+        Ok(Ast::Command {
+            name,
+            maybe_subcommand,
+            words,
+        })
     }
 
-    fn try_parse_expression(tokens: &[Token]) -> Result<(Vec<u8>, usize)> {
-        match (tokens.get(0), tokens.get(1)) {
-            (Some(Token::Identifier(data)), ..) => Ok((data.to_vec(), 1)),
-            (Some(Token::LSquareBracket), ..) => {
-                let body = Parser::try_extract_square_block(tokens)?;
-                Ok((Parser::parse_vec(&tokens[..body.len() + 2]), body.len() + 2))
-            }
-            (Some(Token::Dollar), Some(Token::LCurlyBracket)) => {
-                let body = Parser::try_extract_block(&tokens[1..])?;
-                Ok((Parser::parse_vec(&tokens[..body.len() + 3]), body.len() + 3))
+    fn try_parse_word(&self, tokens: RawWord, kind: ArgumentKind) -> Result<Word> {
+        if let (Some(Token::Expansion), true) = (tokens.first(), tokens.len() > 1) {
+            let word = self.try_parse_word(&tokens[1..], ArgumentKind::Word)?;
+            return Ok(Word::Expansion(Box::new(word)));
+        }
+
+        if let Some(inner) = Parser::try_extract_braced(tokens) {
+            return Ok(match kind {
+                ArgumentKind::Script => Word::Script(self.try_parse_body(inner)?),
+                ArgumentKind::Expression => {
+                    Word::Expression(Parser::parse_vec(inner).trim_ascii().to_vec())
+                }
+                ArgumentKind::List => Word::List(
+                    Parser::try_extract_list(inner)?
+                        .into_iter()
+                        .map(Parser::parse_vec)
+                        .collect(),
+                ),
+                _ => Word::Braced(Parser::parse_vec(inner)),
+            });
+        }
+
+        if let (Some(Token::Quote), Some(Token::Quote)) = (tokens.first(), tokens.last()) {
+            if tokens.len() > 1 && Parser::try_extract_quoted(tokens)? == tokens.len() {
+                let parts = self.try_parse_word_parts(&tokens[1..tokens.len() - 1])?;
+                return Ok(Word::Quoted(parts));
             }
-            _ => {
-                dbg!(&tokens[0]);
-                dbg!(String::from_utf8_lossy(&Parser::parse_vec(tokens)));
-                return Err(ParserFail::Expression);
+        }
+
+        let mut parts = self.try_parse_word_parts(tokens)?;
+        if parts.len() == 1 {
+            match parts.pop() {
+                Some(WordPart::Variable(name)) => return Ok(Word::Variable(name)),
+                Some(WordPart::CommandSubstitution(body)) => {
+                    return Ok(Word::CommandSubstitution(body))
+                }
+                Some(part) => parts.push(part),
+                None => unreachable!(),
             }
         }
+        Ok(Word::Bare(parts))
     }
 
-    fn try_parse_switch(mut tokens: &[Token]) -> Result<(Ast, usize)> {
-        println!("parsing switch");
-        let condition = {
-            let (Token::Dollar, Token::Identifier(data)) = (&tokens[1], &tokens[2]) else {
-                unreachable!();
-            };
-            let mut value = Vec::from(&tokens[1]);
-            value.extend(data);
-            value
-        };
-        let mut consumed = 4; // starts from 4 for the switch keyword, dollar, identifier, curly bracket
-        tokens = &tokens[3..];
-
-        tokens = Parser::try_extract_block(tokens)?;
-        consumed += tokens.len() + 2;
-
-        let mut value_block_or_fallthrough_vec = Vec::new();
-
-        while !tokens.is_empty() {
-            match (
-                tokens.get(0),
-                tokens.get(1),
-                tokens.get(2),
-                tokens.get(3),
-                tokens.get(4),
-            ) {
-                (Some(Token::Newline), ..) => tokens = &tokens[1..],
-                (
-                    Some(Token::Quote),
-                    Some(Token::Identifier(_)),
-                    Some(Token::Quote),
-                    Some(Token::Minus),
-                    Some(Token::Newline),
-                ) => {
-                    // fallthrough
-                    let v = Parser::parse_vec(&tokens[..3]);
-                    tokens = &tokens[5..];
-                    value_block_or_fallthrough_vec.push((v, None));
+    // splits a bare or quoted word into text, variables and command substitutions
+    fn try_parse_word_parts(&self, mut tokens: &[Token]) -> Result<Vec<WordPart>> {
+        let mut parts = Vec::new();
+        let mut text = Vec::new();
+        while let Some(token) = tokens.first() {
+            let (part, rem, consumed) = match (token, tokens.get(1)) {
+                (Token::LSquareBracket, _) => {
+                    let body = Parser::try_extract_square_block(tokens)?;
+                    (
+                        Some(WordPart::CommandSubstitution(self.try_parse_body(body)?)),
+                        b"".as_slice(),
+                        body.len() + 2,
+                    )
                 }
-                (
-                    Some(Token::Quote),
-                    Some(Token::Identifier(_)),
-                    Some(Token::Quote),
-                    Some(Token::LCurlyBracket),
-                    ..,
-                ) => {
-                    // no fallthrough
-                    let v = Parser::parse_vec(&tokens[..3]);
-                    tokens = &tokens[3..];
-                    let body_tokens = Parser::try_extract_block(tokens)?;
-                    tokens = &tokens[body_tokens.len() + 2..];
-                    let (body, _) = Parser::try_parse(body_tokens)?;
-                    value_block_or_fallthrough_vec.push((v, Some(body)));
-                }
-                (Some(Token::Identifier(value)), Some(Token::LCurlyBracket), ..)
-                    if value == b"default" =>
+                (Token::Dollar, Some(t))
+                    if matches!(
+                        t,
+                        Token::LCurlyBracket | Token::Identifier(_) | Token::DoubleColon
+                    ) || t.is_keyword() =>
                 {
-                    // default
-                    // TODO: assert this is the last condition-block
-                    tokens = &tokens[1..];
-                    let body_tokens = Parser::try_extract_block(tokens)?;
-                    tokens = &tokens[body_tokens.len() + 2..];
-                    let (body, _) = Parser::try_parse(body_tokens)?;
-                    value_block_or_fallthrough_vec.push((value.to_vec(), Some(body)));
+                    let (variable, rem, consumed) = self.try_parse_variable(&tokens[1..])?;
+                    (Some(WordPart::Variable(variable)), rem, consumed + 1)
                 }
                 _ => {
-                    dbg!(&tokens[0]);
-                    return Err(ParserFail::SwitchBlock);
+                    text.extend(Vec::from(token));
+                    (None, b"".as_slice(), 1)
                 }
+            };
+            if let Some(part) = part {
+                if !text.is_empty() {
+                    parts.push(WordPart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(part);
+                // text left over from splitting an identifier belongs after the variable
+                text.extend_from_slice(rem);
             }
+            tokens = &tokens[consumed..];
         }
+        if !text.is_empty() {
+            parts.push(WordPart::Text(text));
+        }
+        Ok(parts)
+    }
 
-        return Ok((
-            Ast::Switch {
-                condition,
-                value_block_or_fallthrough_vec,
-            },
-            consumed,
-        ));
+    // the variable reference after the dollar: namespace separators, identifiers and an array index
+    // returns the reference, the text after the name in its last identifier and the number of tokens
+    fn try_parse_variable<'a>(&self, tokens: &'a [Token]) -> Result<(Variable, &'a [u8], usize)> {
+        if let Some(Token::LCurlyBracket) = tokens.first() {
+            let name_tokens = Parser::try_extract_block(tokens)?;
+            let (maybe_namespace, name) = Parser::split_namespace(Parser::parse_vec(name_tokens));
+            let variable = Variable {
+                maybe_namespace,
+                name,
+                maybe_index: None,
+                is_braced: true,
+            };
+            return Ok((variable, b"", name_tokens.len() + 2));
+        }
+
+        let mut name = Vec::new();
+        let mut rem: &[u8] = b"";
+        let mut consumed = 0;
+        while let Some(token) = tokens.get(consumed) {
+            match token {
+                Token::DoubleColon => name.extend_from_slice(b"::"),
+                Token::Identifier(data) => {
+                    // dots are part of identifiers, but not of variable names
+                    let len = data.iter().take_while(|&&c| c != b'.').count();
+                    name.extend_from_slice(&data[..len]);
+                    if len < data.len() {
+                        rem = &data[len..];
+                        consumed += 1;
+                        break;
+                    }
+                }
+                t if t.is_keyword() => name.extend(Vec::from(t)),
+                _ => break,
+            }
+            consumed += 1;
+        }
+
+        let mut maybe_index = None;
+        if let (Some(Token::LParen), true) = (tokens.get(consumed), rem.is_empty()) {
+            if let Some(len) = tokens[consumed..]
+                .iter()
+                .position(|t| matches!(t, Token::RParen | Token::Newline))
+                .filter(|&len| matches!(tokens[consumed + len], Token::RParen))
+            {
+                let index_tokens = &tokens[consumed + 1..consumed + len];
+                maybe_index = Some(self.try_parse_word_parts(index_tokens)?);
+                consumed += len + 1;
+            }
+        }
+
+        let (maybe_namespace, name) = Parser::split_namespace(name);
+        let variable = Variable {
+            maybe_namespace,
+            name,
+            maybe_index,
+            is_braced: false,
+        };
+        Ok((variable, rem, consumed))
     }
 
-    fn try_parse(mut tokens: &[Token]) -> Result<(Ast, usize)> {
+    fn split_namespace(name: Vec<u8>) -> (Option<Vec<u8>>, Vec<u8>) {
+        match name.windows(2).rposition(|w| w == b"::") {
+            Some(idx) => (Some(name[..idx].to_vec()), name[idx + 2..].to_vec()),
+            None => (None, name),
+        }
+    }
+
+    fn try_parse(&self, mut tokens: &[Token]) -> Result<(Ast, usize)> {
         println!("-> recursive call to try_parse");
         let mut trees = Vec::new();
         let mut total_consumed = 0;
         loop {
-            let (ast, consumed) = match Parser::try_parse_one(tokens)? {
+            let (ast, consumed) = match self.try_parse_one(tokens)? {
                 (None, 0) => break,
-                (None, 1) => {
-                    tokens = &tokens[1..];
-                    total_consumed += 1;
+                (None, consumed) => {
+                    tokens = &tokens[consumed..];
+                    total_consumed += consumed;
                     println!("ate a newline");
                     continue;
                 }
                 (Some(ast), consumed) => (ast, consumed),
-                _ => unreachable!(),
             };
             tokens = &tokens[consumed..];
             total_consumed += consumed;
@@ -337,71 +684,398 @@ impl Parser {
         Ok((Ast::Block(trees), total_consumed))
     }
 
-    fn try_parse_one(tokens: &[Token]) -> Result<(Option<Ast>, usize)> {
-        let (ast, consumed) = match (tokens.get(0), tokens.get(1), tokens.get(2), tokens.get(3)) {
-            (Some(Token::Hash), Some(Token::Other(comment_text)), Some(Token::Newline), ..) => {
-                // comment
-                let ast = Ast::Comment(comment_text.to_vec());
-                Ok((ast, 2))
+    fn is_trailing_comment(tokens: &[Token]) -> bool {
+        match tokens {
+            [Token::Space(_), rem @ ..] => Parser::is_trailing_comment(rem),
+            [Token::Hash, ..] => true,
+            _ => false,
+        }
+    }
+
+    // a body written inline, like `{ incr i }`, has no newline to terminate its last statement
+    fn try_parse_body(&self, tokens: &[Token]) -> Result<Ast> {
+        match tokens.last() {
+            None | Some(Token::Newline) => Ok(self.try_parse(tokens)?.0),
+            Some(_) => {
+                let mut tokens = tokens.to_vec();
+                tokens.push(Token::Newline);
+                Ok(self.try_parse(&tokens)?.0)
             }
-            (Some(Token::KeywordIf), Some(Token::LCurlyBracket), ..) => {
-                Parser::try_parse_if(tokens)
+        }
+    }
+
+    fn try_parse_one(&self, tokens: &[Token]) -> Result<(Option<Ast>, usize)> {
+        match (tokens.first(), tokens.get(1), tokens.get(2)) {
+            (Some(Token::Hash), Some(Token::Other(comment_text)), Some(Token::Newline)) => {
+                // comment
+                let ast = Ast::Comment(comment_text.trim_ascii_start().to_vec());
+                return Ok((Some(ast), 2));
             }
-            (
-                Some(Token::KeywordWhen),
-                Some(Token::Identifier(_)),
-                Some(Token::LCurlyBracket),
-                ..,
-            ) => Parser::try_parse_when(tokens),
-            (Some(Token::KeywordSet), Some(Token::Identifier(_)), ..) => {
-                Parser::try_parse_set(tokens)
+            (Some(Token::Verbatim(data)), ..) => {
+                return Ok((Some(Ast::Verbatim(data.to_vec())), 1))
             }
-            (Some(Token::KeywordNode), ..) => Parser::try_parse_node(tokens),
-            (Some(Token::KeywordSnat), ..) => Parser::try_parse_snat(tokens),
-            (Some(Token::KeywordPool), Some(Token::Identifier(_)), Some(Token::Newline), ..)
-            | (
-                Some(Token::KeywordPool),
-                Some(Token::Dollar),
-                Some(Token::Identifier(_)),
-                Some(Token::Newline),
-                ..,
-            ) => Parser::try_parse_pool(tokens),
-            (Some(Token::KeywordLog), Some(Token::Identifier(_)), Some(Token::Other(_)), ..) => {
-                Parser::try_parse_log(tokens)
+            (Some(Token::Newline), Some(Token::Newline), ..) => {
+                return Ok((Some(Ast::EmptyLine), 1))
             }
-            (
-                Some(Token::KeywordSwitch),
-                Some(Token::Dollar),
-                Some(Token::Identifier(_)),
-                Some(Token::LCurlyBracket),
-                ..,
-            ) => Parser::try_parse_switch(tokens),
-            (
-                Some(Token::Identifier(group)),
-                Some(Token::DoubleColon),
-                Some(Token::Identifier(_)),
-                ..,
-            ) if group == b"UDP" || group == b"GTP" => Parser::try_parse_statement(tokens),
-            (Some(Token::KeywordReturn), Some(Token::Newline), ..) => {
-                Ok((Ast::Statement(Statement::Return { value: None }), 2))
+            (Some(Token::Newline), ..) => return Ok((None, 1)), // eat newline
+            (Some(Token::Space(_)), ..) => return Ok((None, 1)), // i.e. after `[` in `[ cmd ]`
+            // `;# note` after a command, up to the end of the line
+            (Some(Token::Semicolon), ..) if Parser::is_trailing_comment(&tokens[1..]) => {
+                let consumed = tokens
+                    .iter()
+                    .position(|t| matches!(t, Token::Newline))
+                    .unwrap_or(tokens.len());
+                let text = Parser::parse_vec(&tokens[1..consumed]);
+                let comment_text = text.trim_ascii_start()[1..].to_vec(); // without the hash
+                return Ok((Some(Ast::TrailingComment(comment_text)), consumed));
             }
-
-            (Some(Token::Newline), Some(Token::Newline), ..) => Ok((Ast::EmptyLine, 2)),
-            (Some(Token::Newline), _, ..) => return Ok((None, 1)), // eat newline
+            (Some(Token::Semicolon), ..) => return Ok((None, 1)), // end of the previous command
             (None, ..) => return Ok((None, 0)),
-            _ => {
-                dbg!(&tokens[0]);
-                dbg!(&tokens[1]);
-                dbg!(&tokens[2]);
-                dbg!(&tokens[3]);
-                return Err(ParserFail::UnknownAST);
-            } // TODO:
+            _ => {}
+        }
+
+        // the newline or semicolon after the command is left for the caller, to notice empty lines
+        let (words, consumed) = Parser::try_extract_words(tokens)?;
+        if words.is_empty() {
+            return Ok((None, consumed)); // a line continuation without a command
+        }
+        let name = Parser::parse_vec(words[0]);
+        let ast = match name.as_slice() {
+            b"if" => self.try_parse_if(&words),
+            b"when" => self.try_parse_when(&words),
+            b"proc" => self.try_parse_proc(&words),
+            b"namespace" => self.try_parse_namespace(&words),
+            b"catch" => self.try_parse_catch(&words),
+            b"try" => self.try_parse_try(&words),
+            b"switch" => self.try_parse_switch(&words),
+            b"set" => self.try_parse_set(&words),
+            b"log" => self.try_parse_log(&words),
+            b"node" => self.try_parse_node(&words),
+            b"snat" => self.try_parse_snat(&words),
+            b"pool" | b"snatpool" => self.try_parse_pool(&words),
+            b"return" => self.try_parse_return(&words),
+            x if x.starts_with(b"GTP::") => self.try_parse_gtp(&words),
+            x if x.starts_with(b"UDP::") => self.try_parse_udp(&words),
+            _ => self.try_parse_command(&words),
         }?;
         Ok((Some(ast), consumed))
     }
 
+    // commands not known here, or with options and arguments not known here, are parsed like
+    // any other command
+    fn try_parse_gtp(&self, words: &[RawWord]) -> Result<Ast> {
+        let word = |w| self.try_parse_word(w, ArgumentKind::Word);
+        let command = match (Parser::parse_vec(words[0]).as_slice(), &words[1..]) {
+            (b"GTP::header", arguments) => {
+                let Some(arguments) = self.try_parse_gtp_arguments(arguments)? else {
+                    return self.try_parse_command(words);
+                };
+                let fields = arguments
+                    .iter()
+                    .filter(|a| matches!(a, GtpArgument::Word(_)));
+                if fields.count() != 1 {
+                    return self.try_parse_command(words);
+                }
+                GtpCommand::Header { arguments }
+            }
+            (b"GTP::ie", [action, arguments @ ..]) => {
+                let action = match Parser::parse_vec(action).as_slice() {
+                    b"get" => GtpIeAction::Get,
+                    b"count" => GtpIeAction::Count,
+                    b"insert" => GtpIeAction::Insert,
+                    b"remove" => GtpIeAction::Remove,
+                    b"set" => GtpIeAction::Set,
+                    _ => return self.try_parse_command(words),
+                };
+                let Some(arguments) = self.try_parse_gtp_arguments(arguments)? else {
+                    return self.try_parse_command(words);
+                };
+                GtpCommand::Ie { action, arguments }
+            }
+            (b"GTP::parse", [payload]) => GtpCommand::Parse {
+                payload: word(payload)?,
+                maybe_length: None,
+            },
+            (b"GTP::parse", [payload, length]) => GtpCommand::Parse {
+                payload: word(payload)?,
+                maybe_length: Some(word(length)?),
+            },
+            (b"GTP::new", [version, message_type]) => GtpCommand::New {
+                version: word(version)?,
+                message_type: word(message_type)?,
+            },
+            (b"GTP::respond", [message]) => GtpCommand::Respond {
+                message: word(message)?,
+            },
+            (b"GTP::forward", [message]) => GtpCommand::Forward {
+                message: word(message)?,
+            },
+            _ => return self.try_parse_command(words),
+        };
+        Ok(Ast::Gtp(Box::new(command)))
+    }
+
+    // `-name value` options and the other arguments, in the order given, None for an option
+    // that isn't known here
+    fn try_parse_gtp_arguments(&self, mut words: &[RawWord]) -> Result<Option<Vec<GtpArgument>>> {
+        let mut arguments = Vec::new();
+        while let [word, rem @ ..] = words {
+            let name = Parser::parse_vec(word);
+            match (name.as_slice(), rem) {
+                (b"-message" | b"-type" | b"-instance" | b"-index", [value, rem @ ..]) => {
+                    let value = self.try_parse_word(value, ArgumentKind::Word)?;
+                    arguments.push(GtpArgument::Option((name, value)));
+                    words = rem;
+                }
+                (x, _) if x.starts_with(b"-") && !x[1..].iter().all(u8::is_ascii_digit) => {
+                    return Ok(None)
+                }
+                _ => {
+                    let word = self.try_parse_word(word, ArgumentKind::Word)?;
+                    arguments.push(GtpArgument::Word(word));
+                    words = rem;
+                }
+            }
+        }
+        Ok(Some(arguments))
+    }
+
+    // the same as for GTP:: commands
+    fn try_parse_udp(&self, words: &[RawWord]) -> Result<Ast> {
+        let word = |w| self.try_parse_word(w, ArgumentKind::Word);
+        let command = match (Parser::parse_vec(words[0]).as_slice(), &words[1..]) {
+            (b"UDP::payload", [replace, offset, length, data])
+                if Parser::word_is(replace, b"replace") =>
+            {
+                UdpCommand::PayloadReplace {
+                    offset: word(offset)?,
+                    length: word(length)?,
+                    data: word(data)?,
+                }
+            }
+            (b"UDP::payload", arguments) if arguments.len() <= 2 => UdpCommand::Payload {
+                arguments: arguments.iter().copied().map(word).collect::<Result<_>>()?,
+            },
+            (b"UDP::respond", [data]) => UdpCommand::Respond { data: word(data)? },
+            (b"UDP::drop", []) => UdpCommand::Drop,
+            _ => return self.try_parse_command(words),
+        };
+        Ok(Ast::Udp(Box::new(command)))
+    }
+
+    fn try_parse_switch(&self, words: &[RawWord]) -> Result<Ast> {
+        println!("parsing switch");
+        let mut options = Vec::new();
+        let mut rem_words = &words[1..]; // skip the switch keyword
+        while let [option, rem @ ..] = rem_words {
+            let option = match Parser::parse_vec(option).as_slice() {
+                b"-exact" => SwitchOption::Exact,
+                b"-glob" => SwitchOption::Glob,
+                b"-regexp" => SwitchOption::Regexp,
+                b"-nocase" => SwitchOption::NoCase,
+                b"--" => SwitchOption::EndOfOptions,
+                // such as -matchvar or -indexvar
+                x if x.starts_with(b"-") && rem.len() > 1 => return self.try_parse_command(words),
+                _ => break,
+            };
+            options.push(option);
+            rem_words = rem;
+            if option == SwitchOption::EndOfOptions {
+                break;
+            }
+        }
+        // the form with patterns and bodies as separate words is a generic command
+        let [subject, body] = rem_words else {
+            return self.try_parse_command(words);
+        };
+        let Some(body_tokens) = Parser::try_extract_braced(body) else {
+            return self.try_parse_command(words);
+        };
+        let subject = Box::new(self.try_parse_word(subject, ArgumentKind::Word)?);
+
+        // a comment is only recognised in place of a pattern, at the start of a line
+        let mut arms = Vec::new();
+        let mut maybe_pattern = None;
+        let mut idx = 0;
+        while let Some(token) = body_tokens.get(idx) {
+            let is_line_start = matches!(
+                body_tokens[..idx]
+                    .iter()
+                    .rfind(|t| !matches!(t, Token::Space(_))),
+                None | Some(Token::Newline)
+            );
+            match (token, body_tokens.get(idx + 1)) {
+                (Token::Space(_) | Token::Newline, _) => idx += 1,
+                // between a pattern and its body, the switch is written as a generic command
+                (Token::Hash, Some(Token::Other(_))) | (Token::Verbatim(_), _)
+                    if maybe_pattern.is_some() && is_line_start =>
+                {
+                    return self.try_parse_command(words)
+                }
+                (Token::Hash, Some(Token::Other(comment_text))) if is_line_start => {
+                    arms.push(SwitchArm::Comment(comment_text.to_vec()));
+                    idx += 2;
+                }
+                (Token::Verbatim(data), _) => {
+                    arms.push(SwitchArm::Verbatim(data.to_vec()));
+                    idx += 1;
+                }
+                _ => {
+                    let n = Parser::try_extract_list_word(&body_tokens[idx..])?;
+                    let word = &body_tokens[idx..idx + n];
+                    match maybe_pattern.take() {
+                        Some(pattern) => arms.push(self.try_parse_switch_arm(pattern, word)?),
+                        None => maybe_pattern = Some(word),
+                    }
+                    idx += n;
+                }
+            }
+        }
+        if maybe_pattern.is_some() {
+            return self.try_parse_command(words); // a pattern without a body
+        }
+
+        Ok(Ast::Switch {
+            options,
+            subject,
+            arms,
+        })
+    }
+
+    fn try_parse_switch_arm(&self, pattern: RawWord, body: RawWord) -> Result<SwitchArm> {
+        let pattern = self.try_parse_word(pattern, ArgumentKind::Word)?;
+        let maybe_body = match Parser::try_extract_braced(body) {
+            Some(body_tokens) => Some(self.try_parse_body(body_tokens)?),
+            None if Parser::word_is(body, b"-") && !pattern.is_default() => None, // fallthrough
+            None => return Err(ParserFail::SwitchBlock),
+        };
+        Ok(SwitchArm::Pattern {
+            pattern,
+            maybe_body,
+        })
+    }
+
+    // the words of the command at tokens[0] and the number of tokens up to its newline
+    fn try_extract_words(tokens: &[Token]) -> Result<(Vec<RawWord<'_>>, usize)> {
+        let mut words = Vec::new();
+        let mut idx = 0;
+        loop {
+            match (tokens.get(idx), tokens.get(idx + 1)) {
+                (Some(Token::Newline | Token::Semicolon), _) => return Ok((words, idx)),
+                (Some(Token::Space(_)), _) => idx += 1,
+                (Some(Token::Other(data)), Some(Token::Newline)) if data == b"\\" => idx += 2, // line continuation
+                (Some(_), _) => {
+                    let n = Parser::try_extract_word(&tokens[idx..], false)?;
+                    words.push(&tokens[idx..idx + n]);
+                    idx += n;
+                }
+                (None, _) => return Err(ParserFail::NoNewline),
+            }
+        }
+    }
+
+    // the words of a braced list, newlines separate words just like spaces
+    fn try_extract_list(mut tokens: &[Token]) -> Result<Vec<RawWord<'_>>> {
+        let mut words = Vec::new();
+        while let Some(token) = tokens.first() {
+            match token {
+                Token::Space(_) | Token::Newline => tokens = &tokens[1..],
+                _ => {
+                    let n = Parser::try_extract_list_word(tokens)?;
+                    words.push(&tokens[..n]);
+                    tokens = &tokens[n..];
+                }
+            }
+        }
+        Ok(words)
+    }
+
+    fn try_extract_list_elements(word: RawWord) -> Result<Vec<Vec<u8>>> {
+        match Parser::try_extract_braced(word) {
+            Some(inner) => Ok(Parser::try_extract_list(inner)?
+                .into_iter()
+                .map(Parser::parse_vec)
+                .collect()),
+            None => Ok(vec![Parser::parse_vec(word)]),
+        }
+    }
+
+    // a semicolon is an ordinary character in a list, such as the body of a switch
+    fn try_extract_list_word(tokens: &[Token]) -> Result<usize> {
+        let mut n = Parser::try_extract_word(tokens, false)?;
+        while let Some(Token::Semicolon) = tokens.get(n) {
+            n += 1 + Parser::try_extract_bare(&tokens[n + 1..], false)?;
+        }
+        Ok(n)
+    }
+
+    // number of tokens of the word starting at tokens[0]
+    // inside a command substitution a closing square bracket ends the word too
+    fn try_extract_word(tokens: &[Token], nested: bool) -> Result<usize> {
+        // argument expansion prefix
+        let start = match tokens.first() {
+            Some(Token::Expansion) => 1,
+            _ => 0,
+        };
+        let len = match tokens.get(start) {
+            Some(Token::LCurlyBracket) => Parser::try_extract_block(&tokens[start..])?.len() + 2,
+            Some(Token::Quote) => Parser::try_extract_quoted(&tokens[start..])?,
+            _ => 0,
+        };
+        // anything glued to a braced or quoted word makes it a bare word
+        Ok(start + len + Parser::try_extract_bare(&tokens[start + len..], nested)?)
+    }
+
+    fn try_extract_bare(tokens: &[Token], nested: bool) -> Result<usize> {
+        let mut idx = 0;
+        while let Some(token) = tokens.get(idx) {
+            idx += match (token, tokens.get(idx + 1)) {
+                (Token::Space(_) | Token::Newline | Token::Semicolon, _) => break,
+                (Token::RSquareBracket, _) if nested => break,
+                (Token::Other(data), Some(Token::Newline)) if data == b"\\" => break,
+                (Token::LSquareBracket, _) => {
+                    Parser::try_extract_square_block(&tokens[idx..])?.len() + 2
+                }
+                (Token::Dollar, Some(Token::LCurlyBracket)) => {
+                    Parser::try_extract_block(&tokens[idx + 1..])?.len() + 3
+                }
+                _ => 1,
+            };
+        }
+        Ok(idx)
+    }
+
+    // number of tokens of the quoted word starting at tokens[0], quotes included
+    fn try_extract_quoted(tokens: &[Token]) -> Result<usize> {
+        assert!(matches!(tokens.first(), Some(Token::Quote)));
+        let mut idx = 1;
+        loop {
+            idx += match (tokens.get(idx), tokens.get(idx + 1)) {
+                (Some(Token::Quote), _) => return Ok(idx + 1),
+                (Some(Token::LSquareBracket), _) => {
+                    Parser::try_extract_square_block(&tokens[idx..])?.len() + 2
+                }
+                (Some(Token::Dollar), Some(Token::LCurlyBracket)) => {
+                    Parser::try_extract_block(&tokens[idx + 1..])?.len() + 3
+                }
+                (Some(_), _) => 1,
+                (None, _) => return Err(ParserFail::QuoteMismatch),
+            };
+        }
+    }
+
+    // the tokens between the brackets, if the word is a single braced word
+    fn try_extract_braced(word: RawWord<'_>) -> Option<&[Token]> {
+        match word.first() {
+            Some(Token::LCurlyBracket) => Parser::try_extract_block(word)
+                .ok()
+                .filter(|inner| inner.len() + 2 == word.len()),
+            _ => None,
+        }
+    }
+
     fn try_extract_block(tokens: &[Token]) -> Result<&[Token]> {
-        assert!(matches!(tokens.get(0), Some(Token::LCurlyBracket)));
+        assert!(matches!(tokens.first(), Some(Token::LCurlyBracket)));
         let mut depth = 0;
         for (idx, token) in tokens.iter().enumerate() {
             match token {
@@ -416,38 +1090,182 @@ impl Parser {
         Err(ParserFail::BracketMismatch)
     }
 
+    // the contents of a command substitution is a script, so brackets inside its words don't count
     fn try_extract_square_block(tokens: &[Token]) -> Result<&[Token]> {
-        assert!(matches!(tokens.get(0), Some(Token::LSquareBracket)));
-        let mut depth = 0;
-        for (idx, token) in tokens.iter().enumerate() {
-            match token {
-                Token::LSquareBracket => depth += 1,
-                Token::RSquareBracket => depth -= 1,
-                _ => {}
-            }
-            if depth == 0 {
-                return Ok(&tokens[1..idx]);
+        assert!(matches!(tokens.first(), Some(Token::LSquareBracket)));
+        let mut idx = 1;
+        loop {
+            match tokens.get(idx) {
+                Some(Token::RSquareBracket) => return Ok(&tokens[1..idx]),
+                Some(Token::Space(_) | Token::Newline | Token::Semicolon) => idx += 1,
+                Some(Token::Other(data)) if data == b"\\" => idx += 1,
+                Some(_) => idx += Parser::try_extract_word(&tokens[idx..], true)?,
+                None => return Err(ParserFail::BracketMismatch),
             }
         }
-        Err(ParserFail::BracketMismatch)
     }
 
-    fn try_extract_until_newline(tokens: &[Token]) -> Result<&[Token]> {
-        let start = 0;
-        let end = tokens
-            .iter()
-            .enumerate()
-            .take_while(|(_, t)| !matches!(t, Token::Newline))
-            .last()
-            .map(|(idx, _)| idx)
-            .unwrap_or(0);
-        match tokens.get(end + 1) {
-            Some(Token::Newline) => Ok(&tokens[start..=end]),
-            _ => Err(ParserFail::NoNewline),
-        }
+    fn word_is(word: RawWord, text: &[u8]) -> bool {
+        Parser::parse_vec(word) == text
     }
 
     fn parse_vec(tokens: &[Token]) -> Vec<u8> {
-        tokens.into_iter().flat_map(|t| Vec::from(t)).collect()
+        tokens.iter().flat_map(Vec::from).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn word_parts(source: &[u8]) -> Vec<WordPart> {
+        let tokens = Lexer::new().lex(source.to_vec()).unwrap();
+        let parser = Parser::with_signatures(Signatures::builtin());
+        parser
+            .try_parse_word_parts(&tokens[..tokens.len() - 1])
+            .unwrap()
+    }
+
+    #[test]
+    fn text_after_a_variable_name_stays_after_it() {
+        let parts = word_parts(b"$host.example.com");
+        let [WordPart::Variable(variable), WordPart::Text(text)] = parts.as_slice() else {
+            panic!("expected a variable and text");
+        };
+        assert_eq!(variable.name, b"host");
+        assert_eq!(text, b".example.com");
+
+        let parts = word_parts(b"ip=$ip.$port");
+        let [WordPart::Text(before), WordPart::Variable(ip), WordPart::Text(dot), WordPart::Variable(port)] =
+            parts.as_slice()
+        else {
+            panic!("expected text, a variable, text and a variable");
+        };
+        assert_eq!(before, b"ip=");
+        assert_eq!(ip.name, b"ip");
+        assert_eq!(dot, b".");
+        assert_eq!(port.name, b"port");
+    }
+
+    #[test]
+    fn log_tells_a_destination_from_a_facility() {
+        for (source, destination, facility) in [
+            (&b"log local0. $msg"[..], None, Some(&b"local0."[..])),
+            (b"log 10.0.0.1:514 $msg", Some(&b"10.0.0.1:514"[..]), None),
+            (b"log 10.0.0.1 $msg", Some(b"10.0.0.1"), None),
+        ] {
+            let tokens = Lexer::new().lex(source.to_vec()).unwrap();
+            let parser = Parser::with_signatures(Signatures::builtin());
+            let Ok((
+                Some(Ast::Statement(Statement::Log {
+                    maybe_destination,
+                    maybe_facility,
+                    ..
+                })),
+                _,
+            )) = parser.try_parse_one(&tokens)
+            else {
+                panic!("expected a log statement");
+            };
+            assert_eq!(maybe_destination.as_deref(), destination);
+            assert_eq!(maybe_facility.as_deref(), facility);
+        }
+    }
+
+    fn parse(source: &[u8]) -> Vec<Ast> {
+        let tokens = Lexer::new().lex(source.to_vec()).unwrap();
+        let Ok(Ast::Block(trees)) = Parser::with_signatures(Signatures::builtin()).parse(&tokens)
+        else {
+            panic!("expected a block");
+        };
+        trees
+    }
+
+    #[test]
+    fn a_hash_after_a_semicolon_starts_a_comment_only_between_commands() {
+        let trees = parse(b"set y 1 ;# note\n");
+        let [Ast::Statement(Statement::Set { .. }), Ast::TrailingComment(text)] = trees.as_slice()
+        else {
+            panic!("expected a set and a trailing comment");
+        };
+        assert_eq!(text, b" note");
+
+        for source in [&b"set a \"x; #y\"\n"[..], b"set a {x; #y}\n"] {
+            let trees = parse(source);
+            assert!(matches!(
+                trees.as_slice(),
+                [Ast::Statement(Statement::Set { .. })]
+            ));
+        }
+    }
+
+    #[test]
+    fn addresses() {
+        let parts = |text: &str| match Parser::try_parse_address(text.as_bytes()) {
+            Some(Address::Literal {
+                ip,
+                maybe_route_domain,
+                maybe_port,
+            }) => Some((
+                String::from_utf8(ip).unwrap(),
+                maybe_route_domain.map(|x| String::from_utf8(x).unwrap()),
+                maybe_port.map(|x| String::from_utf8(x).unwrap()),
+            )),
+            _ => None,
+        };
+        let some = |ip: &str, route_domain: Option<&str>, port: Option<&str>| {
+            Some((
+                ip.into(),
+                route_domain.map(Into::into),
+                port.map(Into::into),
+            ))
+        };
+        assert_eq!(parts("10.0.0.1"), some("10.0.0.1", None, None));
+        assert_eq!(parts("10.0.0.1:80"), some("10.0.0.1", None, Some("80")));
+        assert_eq!(
+            parts("10.0.0.1%2:80"),
+            some("10.0.0.1", Some("2"), Some("80"))
+        );
+        assert_eq!(parts("2001:db8::1"), some("2001:db8::1", None, None));
+        assert_eq!(
+            parts("2001:db8::1.80"),
+            some("2001:db8::1", None, Some("80"))
+        );
+        assert_eq!(
+            parts("fe80::1%3.443"),
+            some("fe80::1", Some("3"), Some("443"))
+        );
+        // an IPv4 address embedded in IPv6, with and without a port
+        assert_eq!(
+            parts("::ffff:10.0.0.1"),
+            some("::ffff:10.0.0.1", None, None)
+        );
+        assert_eq!(
+            parts("::ffff:10.0.0.1.80"),
+            some("::ffff:10.0.0.1", None, Some("80"))
+        );
+        for text in ["", "10.0.0", "10.0.0.1:http", "10.0.0.1%x", "cafe"] {
+            assert_eq!(parts(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn a_region_in_a_switch_body_is_kept_as_arms() {
+        let source = b"switch $x {\n  a { b }\n  # tclfmt: off\n  1   { c }\n  # tclfmt: on\n}\n";
+        let trees = parse(source);
+        let [Ast::Switch { arms, .. }] = trees.as_slice() else {
+            panic!("expected a switch");
+        };
+        let [SwitchArm::Pattern { .. }, SwitchArm::Comment(_), SwitchArm::Verbatim(data), SwitchArm::Comment(_)] =
+            arms.as_slice()
+        else {
+            panic!("expected an arm, a comment, a region and a comment");
+        };
+        assert_eq!(data, b"  1   { c }\n");
+
+        // a pattern with its body in the region is a generic command
+        let source = b"switch $x {\n  a\n  # tclfmt: skip\n  { c }\n}\n";
+        assert!(matches!(parse(source).as_slice(), [Ast::Command { .. }]));
     }
 }
@@ -0,0 +1,145 @@
+// Formatting of a part of a file, such as an editor selection or the hunk of a change.
+// The whole file is formatted, for the indentation of the part to be right, and only the edits
+// inside the commands that intersect the part are kept. Everything else is left as it was read.
+
+use std::ops::Range;
+
+use crate::diff::{self, Edit, Granularity};
+
+#[derive(Clone, Copy)]
+pub enum Selection {
+    Lines(usize, usize), // first and last line, starting at 1
+    Bytes(usize, usize), // start and end offset, the end excluded
+}
+
+impl Selection {
+    // `--lines 10:20` or `--bytes 120:480`
+    pub fn parse(flag: &str, value: &str) -> Option<Self> {
+        let (start, end) = value.split_once(':')?;
+        let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+        match flag {
+            "--lines" if 0 < start && start <= end => Some(Self::Lines(start, end)),
+            "--bytes" if start <= end => Some(Self::Bytes(start, end)),
+            _ => None,
+        }
+    }
+}
+
+// `formatted` is the whole of `original` formatted
+pub fn format_range(original: &[u8], formatted: &[u8], selection: Selection) -> Vec<u8> {
+    let Some(bytes) = command_bytes(original, selection) else {
+        return original.to_vec();
+    };
+    let edits: Vec<Edit> = diff::edits(original, formatted, Granularity::Token)
+        .into_iter()
+        .filter(|edit| bytes.start <= edit.range.start && edit.range.end <= bytes.end)
+        // an insertion at the end is at the start of the line after the selection
+        .filter(|edit| !(edit.range.is_empty() && edit.range.start == bytes.end))
+        .collect();
+    diff::apply(original, &edits)
+}
+
+// the bytes of the whole lines of the commands the selection intersects
+fn command_bytes(buf: &[u8], selection: Selection) -> Option<Range<usize>> {
+    let lines = diff::lines(buf);
+    let (mut first, mut last) = match selection {
+        Selection::Lines(start, end) => (start - 1, (end - 1).min(lines.len().checked_sub(1)?)),
+        Selection::Bytes(start, end) => {
+            let line_of = |offset: usize| lines.iter().position(|l| offset < l.end);
+            let first = line_of(start)?;
+            (
+                first,
+                line_of(end.max(start + 1) - 1).unwrap_or(lines.len() - 1),
+            )
+        }
+    };
+    if first > last {
+        return None;
+    }
+
+    // grow until no bracket is left open or closed and no line is continued across the edges
+    loop {
+        let (unmatched_closes, unclosed_opens) = balance(&buf[lines[first].start..lines[last].end]);
+        let is_continued = |idx: usize| buf[lines[idx].clone()].trim_ascii_end().ends_with(b"\\");
+        let grow_up = first > 0 && (unmatched_closes > 0 || is_continued(first - 1));
+        let grow_down = last + 1 < lines.len() && (unclosed_opens > 0 || is_continued(last));
+        if !grow_up && !grow_down {
+            break;
+        }
+        first -= grow_up as usize;
+        last += grow_down as usize;
+    }
+    Some(lines[first].start..lines[last].end)
+}
+
+// (closing brackets without an opening one, opening brackets without a closing one)
+// braces and square brackets are counted alike, quotes are not looked at
+fn balance(text: &[u8]) -> (usize, usize) {
+    let (mut unmatched_closes, mut depth) = (0, 0);
+    let mut is_escaped = false;
+    for &c in text {
+        match c {
+            _ if is_escaped => is_escaped = false,
+            b'\\' => is_escaped = true,
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' if depth == 0 => unmatched_closes += 1,
+            b'}' | b']' => depth -= 1,
+            _ => {}
+        }
+    }
+    (unmatched_closes, depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = b"set a 1\nif {$a} {\n    set b [list \\\n        2]\n}\nset c 3\n";
+
+    #[test]
+    fn a_selection_grows_to_whole_commands() {
+        assert_eq!(command_bytes(SOURCE, Selection::Lines(1, 1)), Some(0..8));
+        // the if, from its first line or its closing brace
+        assert_eq!(command_bytes(SOURCE, Selection::Lines(2, 3)), Some(8..49));
+        assert_eq!(command_bytes(SOURCE, Selection::Lines(5, 5)), Some(8..49));
+        // a continued line and the one after it, inside the braces of the if
+        assert_eq!(command_bytes(SOURCE, Selection::Lines(3, 3)), Some(18..47));
+        assert_eq!(command_bytes(SOURCE, Selection::Lines(4, 4)), Some(18..47));
+        assert_eq!(
+            command_bytes(SOURCE, Selection::Bytes(22, 25)),
+            Some(18..47)
+        );
+        assert_eq!(command_bytes(SOURCE, Selection::Lines(6, 9)), Some(49..57));
+        // an empty byte range is the line it's on
+        assert_eq!(command_bytes(SOURCE, Selection::Bytes(3, 3)), Some(0..8));
+    }
+
+    #[test]
+    fn a_selection_past_the_end_is_nothing() {
+        assert_eq!(command_bytes(SOURCE, Selection::Lines(7, 8)), None);
+        assert_eq!(command_bytes(SOURCE, Selection::Bytes(57, 70)), None);
+        assert_eq!(command_bytes(b"", Selection::Lines(1, 1)), None);
+    }
+
+    #[test]
+    fn selections() {
+        assert!(matches!(
+            Selection::parse("--lines", "2:5"),
+            Some(Selection::Lines(2, 5))
+        ));
+        assert!(matches!(
+            Selection::parse("--bytes", "0:0"),
+            Some(Selection::Bytes(0, 0))
+        ));
+        assert!(Selection::parse("--lines", "0:5").is_none());
+        assert!(Selection::parse("--lines", "5:2").is_none());
+        assert!(Selection::parse("--bytes", "12").is_none());
+    }
+
+    #[test]
+    fn brackets() {
+        assert_eq!(balance(b"} x {"), (1, 1));
+        assert_eq!(balance(b"[a {b}]"), (0, 0));
+        assert_eq!(balance(b"\\{ \\]"), (0, 0));
+    }
+}
@@ -0,0 +1,372 @@
+// Declarative description of which arguments of a command are scripts, expressions,
+// variable names or plain words. See signatures.txt for the format.
+
+const BUILTIN: &str = include_str!("signatures.txt");
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArgumentKind {
+    Script,
+    Expression,
+    Variable,
+    List,
+    Word,
+    Subcommand, // a literal first argument, such as `insert` in `HTTP::header insert`
+}
+
+#[derive(Debug)]
+pub enum SignatureFail {
+    UnknownKind(Vec<u8>), // <something> that isn't a known argument kind
+    GroupMismatch(usize), // unbalanced parens, on the given line
+}
+type Result<T> = std::result::Result<T, SignatureFail>;
+
+impl std::fmt::Display for SignatureFail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownKind(kind) => {
+                write!(
+                    f,
+                    "unknown argument kind <{}>",
+                    String::from_utf8_lossy(kind)
+                )
+            }
+            Self::GroupMismatch(line) => write!(f, "unbalanced group on line {}", line),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Atom {
+    Kind(ArgumentKind),
+    Literal(Vec<u8>),
+    Level,                 // `N` or `#N`, a word that uplevel takes for a level
+    Group(Vec<Vec<Item>>), // alternatives
+}
+
+#[derive(Clone)]
+struct Item {
+    atom: Atom,
+    min: usize,
+    max: usize,
+}
+
+#[derive(Clone)]
+struct Signature {
+    name: Vec<u8>,
+    pattern: Vec<Item>,
+}
+
+#[derive(Clone)]
+pub struct Signatures {
+    signatures: Vec<Signature>,
+}
+
+impl Signatures {
+    pub fn builtin() -> Self {
+        Signatures::parse(BUILTIN.as_bytes()).expect("Failed to parse builtin signatures")
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let mut signatures = Vec::new();
+        for (idx, line) in buf.split(|&c| c == b'\n').enumerate() {
+            let mut words = split_words(line).into_iter();
+            let Some(name) = words.next() else {
+                continue;
+            };
+            let words: Vec<Vec<u8>> = words.collect(); // none for a command without arguments
+            let (pattern, rem) = parse_alternative(&words, idx + 1)?;
+            if !rem.is_empty() {
+                return Err(SignatureFail::GroupMismatch(idx + 1));
+            }
+            signatures.push(Signature { name, pattern });
+        }
+        Ok(Self { signatures })
+    }
+
+    // signatures from `other` take precedence over the existing ones
+    pub fn extend(&mut self, other: Signatures) {
+        let mut signatures = other.signatures;
+        signatures.append(&mut self.signatures);
+        self.signatures = signatures;
+    }
+
+    // returns the kind of every argument, or None if no signature of the command matches
+    pub fn resolve(&self, name: &[u8], arguments: &[Vec<u8>]) -> Option<Vec<ArgumentKind>> {
+        let matcher = Matcher { arguments };
+        self.signatures
+            .iter()
+            .filter(|s| s.name == name)
+            .find_map(|s| matcher.match_items(&s.pattern, 0, &|pos| matcher.accept(pos)))
+            .map(|mut kinds| {
+                kinds.sort_by_key(|(pos, _)| *pos);
+                kinds.into_iter().map(|(_, kind)| kind).collect()
+            })
+    }
+}
+
+// backtracking matcher, every continuation receives the index of the next unmatched argument
+struct Matcher<'a> {
+    arguments: &'a [Vec<u8>],
+}
+
+type Kinds = Vec<(usize, ArgumentKind)>;
+
+impl Matcher<'_> {
+    fn accept(&self, pos: usize) -> Option<Kinds> {
+        (pos == self.arguments.len()).then(Vec::new)
+    }
+
+    fn match_items(
+        &self,
+        items: &[Item],
+        pos: usize,
+        k: &dyn Fn(usize) -> Option<Kinds>,
+    ) -> Option<Kinds> {
+        match items.split_first() {
+            None => k(pos),
+            Some((item, rem)) => {
+                self.match_item(item, 0, pos, &|pos| self.match_items(rem, pos, k))
+            }
+        }
+    }
+
+    fn match_item(
+        &self,
+        item: &Item,
+        n: usize,
+        pos: usize,
+        k: &dyn Fn(usize) -> Option<Kinds>,
+    ) -> Option<Kinds> {
+        // greedy, try one more repetition first
+        if n < item.max {
+            // an empty repetition would never terminate
+            let more = self.match_atom(&item.atom, pos, &|next| {
+                (next > pos)
+                    .then(|| self.match_item(item, n + 1, next, k))
+                    .flatten()
+            });
+            if more.is_some() {
+                return more;
+            }
+        }
+        if n >= item.min {
+            k(pos)
+        } else {
+            None
+        }
+    }
+
+    fn match_atom(
+        &self,
+        atom: &Atom,
+        pos: usize,
+        k: &dyn Fn(usize) -> Option<Kinds>,
+    ) -> Option<Kinds> {
+        let argument = self.arguments.get(pos);
+        match atom {
+            Atom::Kind(kind) => {
+                argument?;
+                let mut kinds = k(pos + 1)?;
+                kinds.push((pos, *kind));
+                Some(kinds)
+            }
+            Atom::Literal(literal) if argument == Some(literal) => {
+                let mut kinds = k(pos + 1)?;
+                let kind = match pos == 0 && !literal.starts_with(b"-") {
+                    true => ArgumentKind::Subcommand,
+                    false => ArgumentKind::Word,
+                };
+                kinds.push((pos, kind));
+                Some(kinds)
+            }
+            Atom::Literal(_) => None,
+            Atom::Level if argument.is_some_and(|a| is_level(a)) => {
+                let mut kinds = k(pos + 1)?;
+                kinds.push((pos, ArgumentKind::Word));
+                Some(kinds)
+            }
+            Atom::Level => None,
+            Atom::Group(alternatives) => alternatives
+                .iter()
+                .find_map(|items| self.match_items(items, pos, k)),
+        }
+    }
+}
+
+// parses a sequence of items up to a `|` or `)`, returns the remaining words
+fn parse_alternative(mut words: &[Vec<u8>], line: usize) -> Result<(Vec<Item>, &[Vec<u8>])> {
+    let mut items = Vec::new();
+    while let Some(word) = words.first() {
+        let (atom, quantifier) = match word.as_slice() {
+            b"|" => break,
+            x if x.starts_with(b")") => break,
+            b"(" => {
+                let mut alternatives = Vec::new();
+                loop {
+                    let (items, rem) = parse_alternative(&words[1..], line)?;
+                    alternatives.push(items);
+                    words = rem;
+                    match words.first().map(|w| w.as_slice()) {
+                        Some(b"|") => continue,
+                        Some(x) if x.starts_with(b")") => break,
+                        _ => return Err(SignatureFail::GroupMismatch(line)),
+                    }
+                }
+                (Atom::Group(alternatives), &words[0][1..])
+            }
+            x => {
+                let (atom, quantifier) = split_quantifier(x);
+                match atom.strip_prefix(b"<").and_then(|a| a.strip_suffix(b">")) {
+                    Some(b"level") => (Atom::Level, quantifier),
+                    Some(kind) => (Atom::Kind(parse_kind(kind)?), quantifier),
+                    None => (Atom::Literal(atom.to_vec()), quantifier),
+                }
+            }
+        };
+        let (min, max) = match quantifier {
+            b"?" => (0, 1),
+            b"*" => (0, usize::MAX),
+            b"+" => (1, usize::MAX),
+            _ => (1, 1),
+        };
+        items.push(Item { atom, min, max });
+        words = &words[1..];
+    }
+    Ok((items, words))
+}
+
+fn parse_kind(kind: &[u8]) -> Result<ArgumentKind> {
+    match kind {
+        b"script" => Ok(ArgumentKind::Script),
+        b"expr" => Ok(ArgumentKind::Expression),
+        b"var" => Ok(ArgumentKind::Variable),
+        b"list" => Ok(ArgumentKind::List),
+        b"word" => Ok(ArgumentKind::Word),
+        _ => Err(SignatureFail::UnknownKind(kind.to_vec())),
+    }
+}
+
+fn is_level(word: &[u8]) -> bool {
+    let digits = word.strip_prefix(b"#").unwrap_or(word);
+    !digits.is_empty() && digits.iter().all(u8::is_ascii_digit)
+}
+
+fn split_quantifier(word: &[u8]) -> (&[u8], &[u8]) {
+    match word.last() {
+        Some(b'?' | b'*' | b'+') if word.len() > 1 => word.split_at(word.len() - 1),
+        _ => (word, b""),
+    }
+}
+
+// whitespace separated words, stopping at a comment
+fn split_words(line: &[u8]) -> Vec<Vec<u8>> {
+    line.split(|c| c.is_ascii_whitespace())
+        .filter(|w| !w.is_empty())
+        .take_while(|w| !w.starts_with(b"#"))
+        .map(|w| w.to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArgumentKind::*;
+    use super::*;
+
+    fn resolve(signatures: &str, command: &str) -> Option<Vec<ArgumentKind>> {
+        let signatures = Signatures::parse(signatures.as_bytes()).unwrap();
+        let mut words = command.split(' ').map(|w| w.as_bytes().to_vec());
+        let name = words.next().unwrap();
+        signatures.resolve(&name, &words.collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn quantifiers() {
+        let signatures = "unset -nocomplain? <var>+";
+        assert_eq!(
+            resolve(signatures, "unset a b"),
+            Some(vec![Variable, Variable])
+        );
+        assert_eq!(
+            resolve(signatures, "unset -nocomplain a"),
+            Some(vec![Word, Variable])
+        );
+        assert_eq!(resolve(signatures, "unset"), None);
+        assert_eq!(resolve("time <script> <word>?", "time a b c"), None);
+        assert_eq!(resolve("pwd", "pwd"), Some(vec![]));
+    }
+
+    #[test]
+    fn groups_backtrack() {
+        let signatures = "foreach ( <var> <list> )+ <script>";
+        assert_eq!(
+            resolve(signatures, "foreach a b c d e"),
+            Some(vec![Variable, List, Variable, List, Script])
+        );
+        assert_eq!(resolve(signatures, "foreach a b c d"), None);
+        let signatures = "namespace ( current | tail ) <word>*";
+        assert_eq!(
+            resolve(signatures, "namespace tail a"),
+            Some(vec![Subcommand, Word])
+        );
+        assert_eq!(resolve(signatures, "namespace eval a"), None);
+    }
+
+    #[test]
+    fn the_first_matching_line_wins() {
+        let signatures = "eval <script>\neval <word>+\nother <expr>";
+        assert_eq!(resolve(signatures, "eval a"), Some(vec![Script]));
+        assert_eq!(resolve(signatures, "eval a b"), Some(vec![Word, Word]));
+        assert_eq!(resolve(signatures, "unknown a"), None);
+
+        let mut signatures = Signatures::parse(b"eval <script>").unwrap();
+        signatures.extend(Signatures::parse(b"eval <word>").unwrap());
+        assert_eq!(
+            signatures.resolve(b"eval", &[b"a".to_vec()]),
+            Some(vec![Word])
+        );
+    }
+
+    #[test]
+    fn a_literal_first_argument_is_a_subcommand_unless_a_flag() {
+        let signatures = "after cancel <word>+\nafter <word> -periodic? <script>?";
+        assert_eq!(
+            resolve(signatures, "after cancel a"),
+            Some(vec![Subcommand, Word])
+        );
+        assert_eq!(
+            resolve(signatures, "after 10 -periodic a"),
+            Some(vec![Word, Word, Script])
+        );
+        assert_eq!(
+            resolve("unset -nocomplain <var>", "unset -nocomplain a"),
+            Some(vec![Word, Variable])
+        );
+    }
+
+    #[test]
+    fn a_level_is_a_number() {
+        let signatures = "uplevel <script>\nuplevel <level> <script>\nuplevel <word>+";
+        for level in ["1", "#0"] {
+            assert_eq!(
+                resolve(signatures, &format!("uplevel {level} a")),
+                Some(vec![Word, Script])
+            );
+        }
+        assert_eq!(resolve(signatures, "uplevel a b"), Some(vec![Word, Word]));
+        assert_eq!(resolve(signatures, "uplevel # b"), Some(vec![Word, Word]));
+    }
+
+    #[test]
+    fn malformed_signatures() {
+        assert!(matches!(
+            Signatures::parse(b"a <word>\nb ( <word>"),
+            Err(SignatureFail::GroupMismatch(2))
+        ));
+        assert!(matches!(
+            Signatures::parse(b"a <number>"),
+            Err(SignatureFail::UnknownKind(kind)) if kind == b"number"
+        ));
+        assert!(Signatures::parse(b"# a comment\n\na <word> # trailing").is_ok());
+        Signatures::builtin();
+    }
+}